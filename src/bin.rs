@@ -0,0 +1,63 @@
+use crate::image::MemoryImage;
+
+/* Default limit for flat images, gaps between flash and RAM easily blow past this */
+pub const MAX_BIN_SIZE: u64 = 32 * 1024 * 1024;
+
+pub fn parse(data: &[u8], base_addr: u32) -> Result<MemoryImage, String> {
+    if base_addr as u64 + data.len() as u64 > 1 << 32 {
+        return Err(format!("{} bytes at {:#x} exceed the 32-bit address space", data.len(), base_addr));
+    }
+    let mut image = MemoryImage::new();
+    image.insert(base_addr, data);
+    Ok(image)
+}
+
+pub fn write(image: &MemoryImage, fill: u8, max_size: u64, buf: &mut Vec<u8>) -> Result<(), String> {
+    let (first, last) = match (image.regions.first(), image.regions.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err("Image has no data to write".to_string()),
+    };
+
+    let size: u64 = last.end() - first.addr as u64;
+    if size > max_size {
+        let mut gaps: Vec<String> = Vec::new();
+        for pair in image.regions.windows(2) {
+            gaps.push(format!("{:#x}..{:#x}", pair[0].end(), pair[1].addr));
+        }
+        return Err(format!("Flat image {:#x}..{:#x} would be {} bytes, more than the {} byte limit (gaps: {})",
+                           first.addr, last.end(), size, max_size, gaps.join(", ")));
+    }
+
+    let start = buf.len();
+    buf.resize(start + size as usize, fill);
+    for region in &image.regions {
+        let i = start + (region.addr - first.addr) as usize;
+        buf[i..i+region.data.len()].copy_from_slice(&region.data);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bin;
+    use crate::image::MemoryImage;
+
+    #[test]
+    fn test_bin() {
+        let mut image = MemoryImage::new();
+        image.insert(0x10000000, &[1, 2, 3]);
+        image.insert(0x10000005, &[4]);
+
+        let mut buf = Vec::<u8>::new();
+        bin::write(&image, 0xff, bin::MAX_BIN_SIZE, &mut buf).unwrap();
+        assert_eq!(buf, vec![1, 2, 3, 0xff, 0xff, 4]);
+
+        let parsed = bin::parse(&buf, 0x10000000).unwrap();
+        assert_eq!(parsed.regions.len(), 1);
+        assert_eq!(parsed.read(0x10000005, 1), Some(&[4][..]));
+
+        image.insert(0x20000000, &[5]);
+        assert!(bin::write(&image, 0, bin::MAX_BIN_SIZE, &mut Vec::new()).is_err());
+        assert!(bin::parse(&[0; 16], 0xfffffff8).is_err());
+    }
+}
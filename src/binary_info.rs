@@ -0,0 +1,365 @@
+use std::fmt;
+
+use crate::image::MemoryImage;
+
+const MARKER_START: u32             = 0x7188ebf2;
+const MARKER_END: u32               = 0xe71aa390;
+/* The header must sit within the first 256 words of the image (after boot2 on RP2040) */
+const HEADER_SEARCH_WORDS: u32      = 256;
+
+const TYPE_ID_AND_INT: u16          = 5;
+const TYPE_ID_AND_STRING: u16       = 6;
+const TYPE_BLOCK_DEVICE: u16        = 7;
+const TYPE_PINS_WITH_FUNC: u16      = 8;
+const TYPE_PINS_WITH_NAME: u16      = 9;
+
+const TAG_RASPBERRY_PI: u16         = 0x5052;
+
+const ID_RP_PROGRAM_NAME: u32               = 0x02031c86;
+const ID_RP_PROGRAM_VERSION_STRING: u32     = 0x11a9bc3a;
+const ID_RP_PROGRAM_BUILD_DATE_STRING: u32  = 0x9da22254;
+const ID_RP_BINARY_END: u32                 = 0x68f465de;
+const ID_RP_PROGRAM_URL: u32                = 0x1856239a;
+const ID_RP_PROGRAM_DESCRIPTION: u32        = 0xb6a07c19;
+const ID_RP_PROGRAM_FEATURE: u32            = 0xa1f4b453;
+const ID_RP_PROGRAM_BUILD_ATTRIBUTE: u32    = 0x4275f0d3;
+const ID_RP_SDK_VERSION: u32                = 0x5360b3ab;
+const ID_RP_PICO_BOARD: u32                 = 0xb63cffbb;
+const ID_RP_BOOT2_NAME: u32                 = 0x7f8882e1;
+
+const PINS_ENCODING_RANGE: u32      = 1;
+const PINS_ENCODING_MULTI: u32      = 2;
+
+pub enum BinaryInfoEntry {
+    IdAndInt { tag: u16, id: u32, value: i32 },
+    IdAndString { tag: u16, id: u32, value: String },
+    BlockDevice { name: String, address: u32, size: u32 },
+    PinsWithFunc { pins: Vec<u32>, func: u32 },
+    PinsWithName { pin_mask: u32, label: String },
+    Unknown { entry_type: u16, tag: u16 }
+}
+
+pub struct BinaryInfo {
+    pub entries: Vec<BinaryInfoEntry>
+}
+
+/* Address translation through the data copy table, for entries living in RAM.
+   All addresses come from the image itself, arithmetic on them must not overflow */
+struct Mapping<'a> {
+    image: &'a MemoryImage,
+    copies: Vec<(u32, u32, u32)>
+}
+
+impl Mapping<'_> {
+    fn translate(&self, addr: u32) -> Option<u32> {
+        for &(source, dest_start, dest_end) in &self.copies {
+            if addr >= dest_start && addr < dest_end {
+                return source.checked_add(addr - dest_start);
+            }
+        }
+        Some(addr)
+    }
+
+    fn read_u32(&self, addr: u32) -> Option<u32> {
+        self.image.read_u32(self.translate(addr)?)
+    }
+
+    fn read_u16(&self, addr: u32) -> Option<u16> {
+        self.image.read_u16(self.translate(addr)?)
+    }
+
+    fn read_str(&self, addr: u32) -> Option<String> {
+        self.image.read_str(self.translate(addr)?)
+    }
+}
+
+fn id_name(tag: u16, id: u32) -> Option<&'static str> {
+    if tag != TAG_RASPBERRY_PI {
+        return None;
+    }
+    match id {
+        ID_RP_PROGRAM_NAME              => Some("Program name"),
+        ID_RP_PROGRAM_VERSION_STRING    => Some("Program version"),
+        ID_RP_PROGRAM_BUILD_DATE_STRING => Some("Build date"),
+        ID_RP_BINARY_END                => Some("Binary end"),
+        ID_RP_PROGRAM_URL               => Some("Program URL"),
+        ID_RP_PROGRAM_DESCRIPTION       => Some("Program description"),
+        ID_RP_PROGRAM_FEATURE           => Some("Program feature"),
+        ID_RP_PROGRAM_BUILD_ATTRIBUTE   => Some("Build attribute"),
+        ID_RP_SDK_VERSION               => Some("SDK version"),
+        ID_RP_PICO_BOARD                => Some("Pico board"),
+        ID_RP_BOOT2_NAME                => Some("Boot2 name"),
+        _                               => None,
+    }
+}
+
+fn pin_func_name(func: u32) -> &'static str {
+    match func {
+        0       => "XIP",
+        1       => "SPI",
+        2       => "UART",
+        3       => "I2C",
+        4       => "PWM",
+        5       => "SIO",
+        6       => "PIO0",
+        7       => "PIO1",
+        8       => "GPCK",
+        9       => "USB",
+        _       => "unknown",
+    }
+}
+
+fn decode_pins(encoding: u32) -> Vec<u32> {
+    let mut pins: Vec<u32> = Vec::new();
+    match encoding & 7 {
+        PINS_ENCODING_RANGE => {
+            let lo = (encoding >> 7) & 0x1f;
+            let hi = (encoding >> 12) & 0x1f;
+            pins.extend(lo..=hi);
+        },
+        PINS_ENCODING_MULTI => {
+            let mut last: Option<u32> = None;
+            for i in 0..5 {
+                let pin = (encoding >> (7 + i*5)) & 0x1f;
+                if last == Some(pin) {
+                    break;
+                }
+                pins.push(pin);
+                last = Some(pin);
+            }
+        },
+        _ => {}
+    }
+    pins
+}
+
+impl BinaryInfo {
+    pub fn parse(image: &MemoryImage) -> Option<BinaryInfo> {
+        let base = image.regions.first()?.addr;
+        let mut header: Option<u32> = None;
+        for i in 0..HEADER_SEARCH_WORDS {
+            let addr = match base.checked_add(i*4) {
+                Some(addr) => addr,
+                None => break,
+            };
+            if image.read_u32(addr) == Some(MARKER_START)
+                && addr.checked_add(16).and_then(|a| image.read_u32(a)) == Some(MARKER_END) {
+                header = Some(addr);
+                break;
+            }
+        }
+        let header = header?;
+
+        let start = image.read_u32(header.checked_add(4)?)?;
+        let end = image.read_u32(header.checked_add(8)?)?;
+        let copy_table = image.read_u32(header.checked_add(12)?)?;
+
+        let mut mapping = Mapping {
+            image,
+            copies: Vec::new()
+        };
+        let mut addr = copy_table;
+        while let Some(source) = image.read_u32(addr) {
+            if source == 0 {
+                break;
+            }
+            let dest_start = image.read_u32(addr.checked_add(4)?)?;
+            let dest_end = image.read_u32(addr.checked_add(8)?)?;
+            mapping.copies.push((source, dest_start, dest_end));
+            addr = match addr.checked_add(12) {
+                Some(addr) => addr,
+                None => break,
+            };
+        }
+
+        let mut info = BinaryInfo {
+            entries: Vec::new()
+        };
+        let mut addr = start;
+        while addr < end {
+            let entry_addr = mapping.read_u32(addr)?;
+            if let Some(entry) = BinaryInfo::parse_entry(&mapping, entry_addr) {
+                info.entries.push(entry);
+            }
+            addr = match addr.checked_add(4) {
+                Some(addr) => addr,
+                None => break,
+            };
+        }
+        Some(info)
+    }
+
+    fn parse_entry(mapping: &Mapping, addr: u32) -> Option<BinaryInfoEntry> {
+        let entry_type = mapping.read_u16(addr)?;
+        let tag = mapping.read_u16(addr.checked_add(2)?)?;
+        let entry = match entry_type {
+            TYPE_ID_AND_INT => BinaryInfoEntry::IdAndInt {
+                tag,
+                id: mapping.read_u32(addr.checked_add(4)?)?,
+                value: mapping.read_u32(addr.checked_add(8)?)? as i32
+            },
+            TYPE_ID_AND_STRING => BinaryInfoEntry::IdAndString {
+                tag,
+                id: mapping.read_u32(addr.checked_add(4)?)?,
+                value: mapping.read_str(mapping.read_u32(addr.checked_add(8)?)?)?
+            },
+            TYPE_BLOCK_DEVICE => BinaryInfoEntry::BlockDevice {
+                name: mapping.read_str(mapping.read_u32(addr.checked_add(4)?)?)?,
+                address: mapping.read_u32(addr.checked_add(8)?)?,
+                size: mapping.read_u32(addr.checked_add(12)?)?
+            },
+            TYPE_PINS_WITH_FUNC => {
+                let encoding = mapping.read_u32(addr.checked_add(4)?)?;
+                BinaryInfoEntry::PinsWithFunc {
+                    pins: decode_pins(encoding),
+                    func: (encoding >> 3) & 0xf
+                }
+            },
+            TYPE_PINS_WITH_NAME => BinaryInfoEntry::PinsWithName {
+                pin_mask: mapping.read_u32(addr.checked_add(4)?)?,
+                label: mapping.read_str(mapping.read_u32(addr.checked_add(8)?)?)?
+            },
+            _ => BinaryInfoEntry::Unknown { entry_type, tag },
+        };
+        Some(entry)
+    }
+
+    pub fn string(&self, id: u32) -> Option<&str> {
+        for entry in &self.entries {
+            if let BinaryInfoEntry::IdAndString { tag: TAG_RASPBERRY_PI, id: entry_id, value } = entry {
+                if *entry_id == id {
+                    return Some(value);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn program_name(&self) -> Option<&str> {
+        self.string(ID_RP_PROGRAM_NAME)
+    }
+}
+
+impl fmt::Display for BinaryInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", "-".repeat(70))?;
+            match entry {
+                BinaryInfoEntry::IdAndInt { tag, id, value } => {
+                    let name = id_name(*tag, *id).map(String::from)
+                        .unwrap_or(format!("{:#06x}/{:#010x}", tag, id));
+                    match *id {
+                        ID_RP_BINARY_END => writeln!(f, "{0: <32} | {1: <35}", name, format!("{:#x}", value))?,
+                        _ => writeln!(f, "{0: <32} | {1: <35}", name, value)?,
+                    }
+                },
+                BinaryInfoEntry::IdAndString { tag, id, value } => {
+                    let name = id_name(*tag, *id).map(String::from)
+                        .unwrap_or(format!("{:#06x}/{:#010x}", tag, id));
+                    writeln!(f, "{0: <32} | {1: <35}", name, value)?;
+                },
+                BinaryInfoEntry::BlockDevice { name, address, size } => {
+                    writeln!(f, "{0: <32} | {1: <35}", "Block device",
+                             format!("{} @ {:#x} ({} bytes)", name, address, size))?;
+                },
+                BinaryInfoEntry::PinsWithFunc { pins, func } => {
+                    let pins: Vec<String> = pins.iter().map(|p| p.to_string()).collect();
+                    writeln!(f, "{0: <32} | {1: <35}", format!("Pins {}", pins.join(", ")), pin_func_name(*func))?;
+                },
+                BinaryInfoEntry::PinsWithName { pin_mask, label } => {
+                    let pins: Vec<String> = (0..32).filter(|p| pin_mask & (1 << p) != 0)
+                        .map(|p: u32| p.to_string()).collect();
+                    writeln!(f, "{0: <32} | {1: <35}", format!("Pins {}", pins.join(", ")), label)?;
+                },
+                BinaryInfoEntry::Unknown { entry_type, tag } => {
+                    writeln!(f, "{0: <32} | {1: <35}", "Unknown entry", format!("type {} tag {:#06x}", entry_type, tag))?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::binary_info::*;
+    use crate::image::MemoryImage;
+
+    fn push_u32(buf: &mut Vec<u8>, val: u32) {
+        buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    #[test]
+    fn test_binary_info() {
+        let base: u32 = 0x10000000;
+        let mut flash = vec![0u8; 0x100];
+        /* Header */
+        push_u32(&mut flash, MARKER_START);
+        push_u32(&mut flash, base + 0x200);
+        push_u32(&mut flash, base + 0x20c);
+        push_u32(&mut flash, base + 0x300);
+        push_u32(&mut flash, MARKER_END);
+        flash.resize(0x200, 0);
+        /* Entry pointers, the second one lives in RAM */
+        push_u32(&mut flash, base + 0x220);
+        push_u32(&mut flash, 0x20000000);
+        push_u32(&mut flash, base + 0x240);
+        flash.resize(0x220, 0);
+        flash.extend_from_slice(&TYPE_ID_AND_STRING.to_le_bytes());
+        flash.extend_from_slice(&TAG_RASPBERRY_PI.to_le_bytes());
+        push_u32(&mut flash, ID_RP_PROGRAM_NAME);
+        push_u32(&mut flash, base + 0x280);
+        flash.resize(0x240, 0);
+        flash.extend_from_slice(&TYPE_PINS_WITH_FUNC.to_le_bytes());
+        flash.extend_from_slice(&TAG_RASPBERRY_PI.to_le_bytes());
+        push_u32(&mut flash, PINS_ENCODING_MULTI | (2 << 3) | (1 << 12) | (1 << 17));
+        flash.resize(0x280, 0);
+        flash.extend_from_slice(b"blink\0");
+        flash.resize(0x2a0, 0);
+        flash.extend_from_slice(b"1.2.3\0");
+        flash.resize(0x300, 0);
+        /* Copy table: RAM 0x20000000.. comes from flash 0x10000400.. */
+        push_u32(&mut flash, base + 0x400);
+        push_u32(&mut flash, 0x20000000);
+        push_u32(&mut flash, 0x20000010);
+        push_u32(&mut flash, 0);
+        flash.resize(0x400, 0);
+        flash.extend_from_slice(&TYPE_ID_AND_STRING.to_le_bytes());
+        flash.extend_from_slice(&TAG_RASPBERRY_PI.to_le_bytes());
+        push_u32(&mut flash, ID_RP_PROGRAM_VERSION_STRING);
+        push_u32(&mut flash, base + 0x2a0);
+
+        let mut image = MemoryImage::new();
+        image.insert(base, &flash);
+        let info = BinaryInfo::parse(&image).expect("Should find binary info");
+
+        assert_eq!(info.entries.len(), 3);
+        assert_eq!(info.program_name(), Some("blink"));
+        assert_eq!(info.string(ID_RP_PROGRAM_VERSION_STRING), Some("1.2.3"));
+        match &info.entries[2] {
+            BinaryInfoEntry::PinsWithFunc { pins, func } => {
+                assert_eq!(pins, &vec![0, 1]);
+                assert_eq!(*func, 2);
+            },
+            _ => panic!("Should be a pins entry"),
+        }
+
+        /* At the top of the address space, where every address read from the image may overflow */
+        let mut image = MemoryImage::new();
+        image.insert(0xffffff00, &MARKER_START.to_le_bytes().repeat(64));
+        assert!(BinaryInfo::parse(&image).is_none());
+
+        let mut top = vec![0u8; 0xe0];
+        push_u32(&mut top, MARKER_START);
+        push_u32(&mut top, 0xfffffff8);
+        push_u32(&mut top, 0xffffffff);
+        push_u32(&mut top, 0xfffffffc);
+        push_u32(&mut top, MARKER_END);
+        push_u32(&mut top, 0);
+        push_u32(&mut top, 0xfffffffe);
+        push_u32(&mut top, 0);
+        let mut image = MemoryImage::new();
+        image.insert(0xffffff00, &top);
+        assert!(BinaryInfo::parse(&image).unwrap().entries.is_empty());
+    }
+}
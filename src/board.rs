@@ -0,0 +1,97 @@
+use std::fmt;
+
+use crate::target::Target;
+
+/* Contents of INFO_UF2.TXT on a bootloader drive */
+pub struct BoardInfo {
+    /* Version after "UF2 Bootloader", e.g. "v3.0" */
+    pub bootloader: Option<String>,
+    pub model: Option<String>,
+    pub board_id: Option<String>,
+    /* Every "Key: Value" line, including Model and Board-ID */
+    pub fields: Vec<(String, String)>
+}
+
+impl fmt::Display for BoardInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "UF2 Bootloader", self.bootloader.as_deref().unwrap_or("unknown"))?;
+        for (key, value) in &self.fields {
+            writeln!(f, "{}", "-".repeat(70))?;
+            writeln!(f, "{0: <32} | {1: <35}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl BoardInfo {
+    pub fn parse(text: &str) -> BoardInfo {
+        let mut info = BoardInfo {
+            bootloader: None,
+            model: None,
+            board_id: None,
+            fields: Vec::new()
+        };
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(version) = line.strip_prefix("UF2 Bootloader") {
+                info.bootloader = Some(version.trim().to_string());
+                continue;
+            }
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            match key {
+                "Model" => info.model = Some(value.to_string()),
+                "Board-ID" => info.board_id = Some(value.to_string()),
+                _ => {},
+            }
+            info.fields.push((key.to_string(), value.to_string()));
+        }
+        info
+    }
+
+    pub fn target(&self, user: &[Target]) -> Option<Target> {
+        self.board_id.as_deref().and_then(|id| Target::for_board(id, user))
+    }
+
+    /* Empty for unknown boards */
+    pub fn family_ids(&self, user: &[Target]) -> Vec<u32> {
+        self.target(user).map(|t| t.family_ids).unwrap_or_default()
+    }
+
+    /* None if the board is unknown, else whether it takes every one of `family_ids` */
+    pub fn accepts(&self, family_ids: &[u32], user: &[Target]) -> Option<bool> {
+        self.target(user).map(|t| family_ids.iter().all(|id| t.family_ids.contains(id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::BoardInfo;
+
+    #[test]
+    fn test_board_info() {
+        let info = BoardInfo::parse("UF2 Bootloader v3.0\r\nModel: Raspberry Pi RP2\r\nBoard-ID: RPI-RP2\r\n");
+        assert_eq!(info.bootloader.as_deref(), Some("v3.0"));
+        assert_eq!(info.model.as_deref(), Some("Raspberry Pi RP2"));
+        assert_eq!(info.board_id.as_deref(), Some("RPI-RP2"));
+        assert_eq!(info.target(&[]).unwrap().name, "rp2040");
+        assert_eq!(info.family_ids(&[]), vec![0xe48bff56]);
+        assert_eq!(info.accepts(&[0xe48bff56], &[]), Some(true));
+        assert_eq!(info.accepts(&[0xe48bff59], &[]), Some(false));
+
+        let info = BoardInfo::parse("UF2 Bootloader 0.6.0 lib/nrfx (v2.0.0) lib/tinyusb (0.10.1-41-gdf0cda2d) s140 6.1.1\n\
+                                     Model: Adafruit Feather nRF52840 Express\n\
+                                     Board-ID: nRF52840-Feather-revD\n\
+                                     Date: Nov 29 2021\n");
+        assert_eq!(info.target(&[]).unwrap().name, "nrf52840");
+        assert_eq!(info.fields.len(), 3);
+
+        let info = BoardInfo::parse("Model: Something\n");
+        assert_eq!(info.bootloader, None);
+        assert_eq!(info.accepts(&[1], &[]), None);
+        assert!(info.family_ids(&[]).is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::image::MemoryImage;
+
+const BYTES_PER_LINE: usize = 16;
+
+/* Identifier made of the name's alphanumeric characters, e.g. "app-v2.h" -> "app_v2_h" */
+pub fn symbol_name(name: &str) -> String {
+    let mut symbol: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    if symbol.is_empty() || symbol.starts_with(|c: char| c.is_ascii_digit()) {
+        symbol.insert(0, '_');
+    }
+    symbol
+}
+
+fn write_bytes(data: &[u8], buf: &mut Vec<u8>) {
+    for line in data.chunks(BYTES_PER_LINE) {
+        let bytes: Vec<String> = line.iter().map(|b| format!("0x{:02x}", b)).collect();
+        buf.extend_from_slice(format!("    {},\n", bytes.join(", ")).as_bytes());
+    }
+}
+
+pub fn write_c(image: &MemoryImage, name: &str, buf: &mut Vec<u8>) {
+    let symbol = symbol_name(name);
+    let upper = symbol.to_uppercase();
+    buf.extend_from_slice(format!("/* Generated by elf2uf2 */\n#ifndef {0}_H\n#define {0}_H\n\n", upper).as_bytes());
+    buf.extend_from_slice(b"#include <stdint.h>\n\n");
+    if let Some(entry) = image.entry {
+        buf.extend_from_slice(format!("#define {}_ENTRY {:#010x}u\n", upper, entry).as_bytes());
+    }
+    buf.extend_from_slice(format!("#define {}_REGION_COUNT {}u\n", upper, image.regions.len()).as_bytes());
+    for (i, region) in image.regions.iter().enumerate() {
+        buf.extend_from_slice(format!("\n#define {}_REGION{}_ADDR {:#010x}u\n", upper, i, region.addr).as_bytes());
+        buf.extend_from_slice(format!("#define {}_REGION{}_SIZE {}u\n", upper, i, region.data.len()).as_bytes());
+        buf.extend_from_slice(format!("static const uint8_t {}_region{}[{}] = {{\n", symbol, i, region.data.len()).as_bytes());
+        write_bytes(&region.data, buf);
+        buf.extend_from_slice(b"};\n");
+    }
+    buf.extend_from_slice(format!("\n#endif /* {}_H */\n", upper).as_bytes());
+}
+
+pub fn write_rust(image: &MemoryImage, name: &str, buf: &mut Vec<u8>) {
+    let upper = symbol_name(name).to_uppercase();
+    buf.extend_from_slice(b"// Generated by elf2uf2\n");
+    if let Some(entry) = image.entry {
+        buf.extend_from_slice(format!("\npub const {}_ENTRY: u32 = {:#010x};\n", upper, entry).as_bytes());
+    }
+    for (i, region) in image.regions.iter().enumerate() {
+        buf.extend_from_slice(format!("\npub const {}_REGION{}_ADDR: u32 = {:#010x};\n", upper, i, region.addr).as_bytes());
+        buf.extend_from_slice(format!("pub static {}_REGION{}: [u8; {}] = [\n", upper, i, region.data.len()).as_bytes());
+        write_bytes(&region.data, buf);
+        buf.extend_from_slice(b"];\n");
+    }
+    buf.extend_from_slice(format!("\npub static {}_REGIONS: [(u32, &[u8]); {}] = [\n", upper, image.regions.len()).as_bytes());
+    for i in 0..image.regions.len() {
+        buf.extend_from_slice(format!("    ({0}_REGION{1}_ADDR, &{0}_REGION{1}),\n", upper, i).as_bytes());
+    }
+    buf.extend_from_slice(b"];\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::carray;
+    use crate::image::MemoryImage;
+
+    #[test]
+    fn test_carray() {
+        let mut image = MemoryImage::new();
+        image.insert(0x10000000, &[0xde, 0xad]);
+        image.insert(0x20000000, &[0x01]);
+        image.entry = Some(0x10000001);
+        assert_eq!(carray::symbol_name("2nd-mcu.fw"), "_2nd_mcu_fw");
+
+        let mut buf = Vec::<u8>::new();
+        carray::write_c(&image, "fw", &mut buf);
+        assert_eq!(String::from_utf8(buf).unwrap(), "\
+/* Generated by elf2uf2 */
+#ifndef FW_H
+#define FW_H
+
+#include <stdint.h>
+
+#define FW_ENTRY 0x10000001u
+#define FW_REGION_COUNT 2u
+
+#define FW_REGION0_ADDR 0x10000000u
+#define FW_REGION0_SIZE 2u
+static const uint8_t fw_region0[2] = {
+    0xde, 0xad,
+};
+
+#define FW_REGION1_ADDR 0x20000000u
+#define FW_REGION1_SIZE 1u
+static const uint8_t fw_region1[1] = {
+    0x01,
+};
+
+#endif /* FW_H */
+");
+
+        let mut buf = Vec::<u8>::new();
+        carray::write_rust(&image, "fw", &mut buf);
+        assert_eq!(String::from_utf8(buf).unwrap(), "\
+// Generated by elf2uf2
+
+pub const FW_ENTRY: u32 = 0x10000001;
+
+pub const FW_REGION0_ADDR: u32 = 0x10000000;
+pub static FW_REGION0: [u8; 2] = [
+    0xde, 0xad,
+];
+
+pub const FW_REGION1_ADDR: u32 = 0x20000000;
+pub static FW_REGION1: [u8; 1] = [
+    0x01,
+];
+
+pub static FW_REGIONS: [(u32, &[u8]); 2] = [
+    (FW_REGION0_ADDR, &FW_REGION0),
+    (FW_REGION1_ADDR, &FW_REGION1),
+];
+");
+    }
+}
@@ -0,0 +1,160 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::board::BoardInfo;
+use crate::target::Target;
+
+pub const INFO_FILE: &str = "INFO_UF2.TXT";
+
+pub struct Drive {
+    pub path: PathBuf,
+    pub info: BoardInfo
+}
+
+/* FAT mount points from /proc/mounts, spaces and other characters are octal escaped */
+pub fn mount_points(mounts: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace().skip(1);
+        let (field, fs_type) = match (fields.next(), fields.next()) {
+            (Some(field), Some(fs_type)) => (field, fs_type),
+            _ => continue,
+        };
+        /* Bootloader drives are always FAT, leaving out /proc, /sys, network and autofs mounts */
+        if !["vfat", "msdos", "fat"].contains(&fs_type) {
+            continue;
+        }
+        /* Escapes are bytes, several of them may make up one UTF-8 character */
+        let mut path: Vec<u8> = Vec::new();
+        let mut bytes = field.bytes();
+        while let Some(b) = bytes.next() {
+            if b == b'\\' {
+                let octal: Vec<u8> = bytes.by_ref().take(3).collect();
+                match std::str::from_utf8(&octal).ok().and_then(|s| u8::from_str_radix(s, 8).ok()) {
+                    Some(byte) => path.push(byte),
+                    None => { path.push(b); path.extend_from_slice(&octal); },
+                }
+            } else {
+                path.push(b);
+            }
+        }
+        paths.push(PathBuf::from(String::from_utf8_lossy(&path).into_owned()));
+    }
+    paths
+}
+
+pub fn default_mounts() -> Vec<PathBuf> {
+    match fs::read_to_string("/proc/mounts") {
+        Ok(mounts) => mount_points(&mounts),
+        Err(_) => Vec::new(),
+    }
+}
+
+/* The usual automount directories, whose subdirectories are searched too */
+pub fn default_roots() -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+    if let Ok(user) = env::var("USER") {
+        roots.push(Path::new("/media").join(&user));
+        roots.push(Path::new("/run/media").join(&user));
+    }
+    roots.push(PathBuf::from("/Volumes"));
+    roots
+}
+
+/* Directories holding INFO_UF2.TXT among the mount points, the roots and the roots' immediate subdirectories */
+pub fn find_drives(mounts: &[PathBuf], roots: &[PathBuf]) -> Vec<Drive> {
+    let mut candidates: Vec<PathBuf> = mounts.to_vec();
+    for root in roots {
+        candidates.push(root.clone());
+        if !root.join(INFO_FILE).is_file() {
+            if let Ok(entries) = fs::read_dir(root) {
+                let mut dirs: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+                dirs.sort();
+                candidates.extend(dirs);
+            }
+        }
+    }
+
+    let mut drives: Vec<Drive> = Vec::new();
+    for path in candidates {
+        let info = match fs::read_to_string(path.join(INFO_FILE)) {
+            Ok(info) => info,
+            Err(_) => continue,
+        };
+        if !drives.iter().any(|d| d.path == path) {
+            drives.push(Drive { path, info: BoardInfo::parse(&info) });
+        }
+    }
+    drives
+}
+
+/* The one drive whose board takes all of `family_ids`, or the only drive of an unknown board */
+pub fn select<'a>(drives: &'a [Drive], family_ids: &[u32], user: &[Target]) -> Result<&'a Drive, String> {
+    if drives.is_empty() {
+        return Err(format!("No mounted drive with {} found", INFO_FILE));
+    }
+    let matching: Vec<&Drive> = drives.iter().filter(|d| d.info.accepts(family_ids, user) == Some(true)).collect();
+    let unknown: Vec<&Drive> = drives.iter().filter(|d| d.info.accepts(family_ids, user).is_none()).collect();
+    let candidates = if matching.is_empty() { unknown } else { matching };
+
+    let list = |drives: &[&Drive]| drives.iter()
+        .map(|d| format!("{} ({})", d.path.display(), d.info.board_id.as_deref().unwrap_or("unknown board")))
+        .collect::<Vec<_>>().join(", ");
+    match candidates.as_slice() {
+        [drive] => Ok(drive),
+        [] => Err(format!("No drive matches family {}: found {}",
+                          family_ids.iter().map(|id| format!("{:#x}", id)).collect::<Vec<_>>().join(", "),
+                          list(&drives.iter().collect::<Vec<_>>()))),
+        _ => Err(format!("Several drives match, pick one with --drive: {}", list(&candidates))),
+    }
+}
+
+/* Synced before returning, the bootloader reboots as soon as it has seen the last block */
+pub fn write(drive: &Path, name: &str, data: &[u8]) -> Result<PathBuf, String> {
+    let path = drive.join(name);
+    let mut file = fs::File::create(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    file.write_all(data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    file.sync_all().map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::deploy::*;
+
+    #[test]
+    fn test_deploy() {
+        assert_eq!(mount_points("/dev/sda1 /media/me/RPI-RP2 vfat rw 0 0\nproc /proc proc rw 0 0\n\
+                                 /dev/sdb1 /mnt/my\\040drive vfat rw 0 0\nserver:/home /home nfs4 rw 0 0\n"),
+                   vec![PathBuf::from("/media/me/RPI-RP2"), PathBuf::from("/mnt/my drive")]);
+        assert_eq!(mount_points("/dev/sda1 /media/caf\\303\\251/\u{e9}t\u{e9} vfat rw 0 0\n"),
+                   vec![PathBuf::from("/media/caf\u{e9}/\u{e9}t\u{e9}")]);
+
+        let root = std::env::temp_dir().join(format!("elf2uf2-deploy-{}", std::process::id()));
+        let pico = root.join("RPI-RP2");
+        let feather = root.join("FTHR840BOOT");
+        fs::create_dir_all(&pico).unwrap();
+        fs::create_dir_all(&feather).unwrap();
+        fs::create_dir_all(root.join("USB")).unwrap();
+        fs::write(pico.join(INFO_FILE), "UF2 Bootloader v3.0\nModel: Raspberry Pi RP2\nBoard-ID: RPI-RP2\n").unwrap();
+        fs::write(feather.join(INFO_FILE), "UF2 Bootloader 0.6.0\nBoard-ID: nRF52840-Feather-revD\n").unwrap();
+
+        let drives = find_drives(&[], std::slice::from_ref(&root));
+        assert_eq!(drives.len(), 2);
+        /* Mount points are not searched below */
+        assert!(find_drives(std::slice::from_ref(&root), &[]).is_empty());
+        assert_eq!(find_drives(std::slice::from_ref(&pico), &[]).len(), 1);
+        assert_eq!(select(&drives, &[0xe48bff56], &[]).unwrap().path, pico);
+        assert_eq!(select(&drives, &[0xada52840], &[]).unwrap().path, feather);
+        assert!(select(&drives, &[0xe48bff59], &[]).is_err());
+        assert!(select(&[], &[0xe48bff56], &[]).is_err());
+
+        let path = write(&pico, "app.uf2", b"data").unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"data");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+use std::fmt;
+
+use crate::elf::Elf32Name;
+use crate::image::MemoryImage;
+
+const PAGE_SIZE: u32 = 256;
+/* Identical bytes between two changes that still count as one changed range */
+const MAX_GAP: u32 = 8;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Kind {
+    Changed,
+    Added,
+    Removed
+}
+
+pub struct DiffRange {
+    pub kind: Kind,
+    pub start: u32,
+    /* u64, a range may end at 2^32 */
+    pub end: u64,
+    /* Bytes that differ, ranges of changed bytes may contain a few identical ones */
+    pub bytes: u32
+}
+
+pub struct Diff {
+    pub ranges: Vec<DiffRange>,
+    /* Zero bytes on one side only, in a page the other side partly covers */
+    pub padding: u32,
+    pub sections: Vec<Elf32Name>,
+    pub symbols: Vec<Elf32Name>
+}
+
+fn push(ranges: &mut Vec<DiffRange>, kind: Kind, addr: u32) {
+    if let Some(last) = ranges.last_mut() {
+        let gap = if kind == Kind::Changed { MAX_GAP } else { 0 };
+        if last.kind == kind && addr as u64 >= last.end && addr as u64 - last.end <= gap as u64 {
+            last.end = addr as u64 + 1;
+            last.bytes += 1;
+            return;
+        }
+    }
+    ranges.push(DiffRange { kind, start: addr, end: addr as u64 + 1, bytes: 1 });
+}
+
+fn covers_page(image: &MemoryImage, addr: u32, page_size: u32) -> bool {
+    let page = (addr - addr % page_size) as u64;
+    image.regions.iter().any(|r| (r.addr as u64) < page + page_size as u64 && page < r.end())
+}
+
+/* Bytes of `image` that are changed in, or missing from, `other`, returns the padding bytes skipped */
+fn compare(image: &MemoryImage, other: &MemoryImage, missing: Kind, page_size: Option<u32>, ranges: &mut Vec<DiffRange>) -> u32 {
+    let mut padding = 0;
+    for region in &image.regions {
+        for (i, byte) in region.data.iter().enumerate() {
+            let addr = region.addr + i as u32;
+            match other.read(addr, 1) {
+                Some(b) if b[0] == *byte => {},
+                Some(_) if missing == Kind::Removed => push(ranges, Kind::Changed, addr),
+                Some(_) => {},
+                None if *byte == 0 && page_size.is_some_and(|size| covers_page(other, addr, size)) => padding += 1,
+                None => push(ranges, missing, addr),
+            }
+        }
+    }
+    padding
+}
+
+impl Diff {
+    /* With a `page_size`, such as that of a UF2 compared to an ELF, zero padding of pages is not a change */
+    pub fn new(old: &MemoryImage, new: &MemoryImage, page_size: Option<u32>) -> Diff {
+        let mut ranges: Vec<DiffRange> = Vec::new();
+        let padding = compare(old, new, Kind::Removed, page_size, &mut ranges)
+            + compare(new, old, Kind::Added, page_size, &mut ranges);
+        ranges.sort_by_key(|r| r.start);
+        Diff {
+            ranges,
+            padding,
+            sections: Vec::new(),
+            symbols: Vec::new()
+        }
+    }
+
+    pub fn bytes(&self, kind: Kind) -> u32 {
+        self.ranges.iter().filter(|r| r.kind == kind).map(|r| r.bytes).sum()
+    }
+
+    pub fn pages(&self) -> Vec<u32> {
+        let mut pages: Vec<u32> = Vec::new();
+        for range in &self.ranges {
+            let mut page = range.start - range.start % PAGE_SIZE;
+            while (page as u64) < range.end {
+                if !pages.contains(&page) {
+                    pages.push(page);
+                }
+                page = match page.checked_add(PAGE_SIZE) {
+                    Some(p) => p,
+                    None => break,
+                };
+            }
+        }
+        pages.sort();
+        pages
+    }
+
+    fn find(names: &[Elf32Name], addr: u32) -> Option<&Elf32Name> {
+        names.iter().find(|n| addr >= n.addr && ((addr - n.addr) as u64) < n.size as u64)
+    }
+
+    /* `symbol+offset`, else the section name */
+    pub fn location(&self, addr: u32) -> Option<String> {
+        if let Some(symbol) = Diff::find(&self.symbols, addr) {
+            return match addr - symbol.addr {
+                0 => Some(symbol.name.clone()),
+                offset => Some(format!("{}+{:#x}", symbol.name, offset)),
+            };
+        }
+        Diff::find(&self.sections, addr).map(|s| s.name.clone())
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for range in &self.ranges {
+            let kind = match range.kind {
+                Kind::Changed => "changed",
+                Kind::Added => "added",
+                Kind::Removed => "removed",
+            };
+            let location = match self.location(range.start) {
+                Some(name) => format!(" in {}", name),
+                None => String::new(),
+            };
+            writeln!(f, "{}", "-".repeat(70))?;
+            writeln!(f, "{0: <32} | {1: <35}", format!("{:#010x}..{:#010x}", range.start, range.end),
+                     format!("{} bytes {}{}", range.bytes, kind, location))?;
+        }
+
+        /* A range counts towards the section it starts in */
+        for section in &self.sections {
+            let bytes: u32 = self.ranges.iter()
+                .filter(|r| Diff::find(&self.sections, r.start).map(|s| s.addr) == Some(section.addr))
+                .map(|r| r.bytes).sum();
+            if bytes > 0 {
+                writeln!(f, "{}", "-".repeat(70))?;
+                writeln!(f, "{0: <32} | {1: <35}", format!("Section {}", section.name),
+                         format!("{} of {} bytes differ", bytes, section.size))?;
+            }
+        }
+
+        if self.padding > 0 {
+            writeln!(f, "{}", "-".repeat(70))?;
+            writeln!(f, "{0: <32} | {1: <35}", "Padding", format!("{} zero bytes in partly covered pages", self.padding))?;
+        }
+
+        writeln!(f, "{}", "=".repeat(70))?;
+        match self.ranges.is_empty() {
+            true => writeln!(f, "{0: <32} | {1: <35}", "Result", "images are identical"),
+            false => writeln!(f, "{0: <32} | {1: <35}", "Result",
+                format!("{} changed, {} added, {} removed bytes in {} pages",
+                        self.bytes(Kind::Changed), self.bytes(Kind::Added),
+                        self.bytes(Kind::Removed), self.pages().len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::*;
+    use crate::elf::Elf32;
+
+    #[test]
+    fn test_diff() {
+        let mut old = MemoryImage::new();
+        old.insert(0x10000000, &[0; 0x200]);
+        old.insert(0x10001000, &[1; 4]);
+        let mut new = MemoryImage::new();
+        new.insert(0x10000000, &[0; 0x200]);
+        new.insert(0x10000010, &[1, 0, 0, 1]);
+        new.insert(0x10000100, &[2]);
+        new.insert(0x20000000, &[3; 2]);
+
+        let diff = Diff::new(&old, &new, None);
+        let found: Vec<(Kind, u32, u64, u32)> = diff.ranges.iter().map(|r| (r.kind, r.start, r.end, r.bytes)).collect();
+        assert_eq!(found, vec![
+            (Kind::Changed, 0x10000010, 0x10000014, 2),
+            (Kind::Changed, 0x10000100, 0x10000101, 1),
+            (Kind::Removed, 0x10001000, 0x10001004, 4),
+            (Kind::Added, 0x20000000, 0x20000002, 2),
+        ]);
+        assert_eq!(diff.pages(), vec![0x10000000, 0x10000100, 0x10001000, 0x20000000]);
+        assert!(Diff::new(&old, &old, None).ranges.is_empty());
+
+        let mut padded = MemoryImage::new();
+        padded.insert(0x10000000, &[0; 0x200]);
+        padded.insert(0x10001000, &[1, 1, 1, 1, 0, 0, 0, 0]);
+        padded.insert(0x10001100, &[0; 4]);
+        let diff = Diff::new(&old, &padded, Some(256));
+        assert_eq!(diff.padding, 4);
+        let found: Vec<(Kind, u32, u64)> = diff.ranges.iter().map(|r| (r.kind, r.start, r.end)).collect();
+        assert_eq!(found, vec![(Kind::Added, 0x10001100, 0x10001104)]);
+        assert_eq!(Diff::new(&padded, &old, Some(256)).padding, 4);
+        assert_eq!(Diff::new(&old, &padded, None).bytes(Kind::Added), 8);
+
+        let mut top = MemoryImage::new();
+        top.insert(0xfffffff0, &[1; 0x10]);
+        let mut changed = MemoryImage::new();
+        changed.insert(0xfffffff0, &[1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 1, 2]);
+        let found: Vec<(Kind, u32, u64, u32)> = Diff::new(&top, &changed, None).ranges.iter()
+            .map(|r| (r.kind, r.start, r.end, r.bytes)).collect();
+        assert_eq!(found, vec![(Kind::Changed, 0xfffffffd, 0x100000000, 2)]);
+        let diff = Diff::new(&top, &MemoryImage::new(), None);
+        assert_eq!((diff.ranges[0].end, diff.pages()), (0x100000000, vec![0xffffff00]));
+
+        let mut buf = Vec::<u8>::new();
+        Elf32::write(40, 0x10000000, &[(0x10000000, &[0; 0x200])], &mut buf);
+        let mut elf = Elf32::new();
+        elf.parse_elf(&buf);
+        let sections = elf.sections();
+        assert_eq!((sections[0].name.as_str(), sections[0].addr, sections[0].size), (".load0", 0x10000000, 0x200));
+
+        let data: Vec<u8> = std::fs::read("test/test_arm.elf")
+                        .expect("Should be a elf file given as input");
+        let mut elf = Elf32::new();
+        elf.parse_elf(&data);
+        let mut diff = Diff::new(&old, &new, None);
+        diff.sections = elf.sections();
+        diff.symbols = elf.symbols(&data);
+        assert_eq!(diff.location(0x1000011d), Some("fft+0x1".to_string()));
+        assert_eq!(diff.location(0x100006d0), Some("cos_lut".to_string()));
+        assert_eq!(diff.location(0x100016d4), Some(".ARM.exidx".to_string()));
+        assert_eq!(diff.location(0x20000000), None);
+    }
+}
@@ -8,7 +8,7 @@ type Elf32Sword    = i32;
 type Elf32Word     = u32;
 
 
-fn slice_to_u16(slice: &[u8]) -> u16 {
+pub fn slice_to_u16(slice: &[u8]) -> u16 {
     assert_eq!(slice.len(), 2);
     let mut val: u16 = 0;
     val |= slice[0] as u16;
@@ -16,7 +16,7 @@ fn slice_to_u16(slice: &[u8]) -> u16 {
     val
 }
 
-fn slice_to_u32(slice: &[u8]) -> u32 {
+pub fn slice_to_u32(slice: &[u8]) -> u32 {
     assert_eq!(slice.len(), 4);
     let mut val: u32 = 0;
     val |= slice[0] as u32;
@@ -31,7 +31,7 @@ pub struct Elf32Ehdr {
     e_type: Elf32Half,
     pub e_machine: Elf32Half,
     e_version: Elf32Word,
-    e_entry: Elf32Addr,
+    pub e_entry: Elf32Addr,
     e_phoff: Elf32Off,
     e_shoff: Elf32Off,
     e_flags: Elf32Word,
@@ -116,6 +116,23 @@ impl fmt::Display for Elf32Ehdr {
 }
 
 impl Elf32Ehdr {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.e_ident);
+        buf.extend_from_slice(&self.e_type.to_le_bytes());
+        buf.extend_from_slice(&self.e_machine.to_le_bytes());
+        buf.extend_from_slice(&self.e_version.to_le_bytes());
+        buf.extend_from_slice(&self.e_entry.to_le_bytes());
+        buf.extend_from_slice(&self.e_phoff.to_le_bytes());
+        buf.extend_from_slice(&self.e_shoff.to_le_bytes());
+        buf.extend_from_slice(&self.e_flags.to_le_bytes());
+        buf.extend_from_slice(&self.e_ehsize.to_le_bytes());
+        buf.extend_from_slice(&self.e_phentsize.to_le_bytes());
+        buf.extend_from_slice(&self.e_phnum.to_le_bytes());
+        buf.extend_from_slice(&self.e_shentsize.to_le_bytes());
+        buf.extend_from_slice(&self.e_shnum.to_le_bytes());
+        buf.extend_from_slice(&self.e_shstrndx.to_le_bytes());
+    }
+
     pub fn new() -> Elf32Ehdr {
             Elf32Ehdr {
                 e_ident: [0; 16],
@@ -204,6 +221,19 @@ impl fmt::Display for Elf32Shdr {
 }
 
 impl Elf32Shdr {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.sh_name.to_le_bytes());
+        buf.extend_from_slice(&self.sh_type.to_le_bytes());
+        buf.extend_from_slice(&self.sh_flags.to_le_bytes());
+        buf.extend_from_slice(&self.sh_addr.to_le_bytes());
+        buf.extend_from_slice(&self.sh_offset.to_le_bytes());
+        buf.extend_from_slice(&self.sh_size.to_le_bytes());
+        buf.extend_from_slice(&self.sh_link.to_le_bytes());
+        buf.extend_from_slice(&self.sh_info.to_le_bytes());
+        buf.extend_from_slice(&self.sh_addralign.to_le_bytes());
+        buf.extend_from_slice(&self.sh_entsize.to_le_bytes());
+    }
+
     pub fn new() -> Elf32Shdr {
         Elf32Shdr {
             sh_name: 0,
@@ -220,15 +250,26 @@ impl Elf32Shdr {
     }
 }
 
-#[allow(dead_code)]
 struct Elf32Sym {
     st_name: Elf32Word,
     st_value: Elf32Addr,
     st_size: Elf32Word,
+    st_info: u8,
     st_other: u8,
     st_shndx: Elf32Half
 }
 
+impl Elf32Sym {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.st_name.to_le_bytes());
+        buf.extend_from_slice(&self.st_value.to_le_bytes());
+        buf.extend_from_slice(&self.st_size.to_le_bytes());
+        buf.push(self.st_info);
+        buf.push(self.st_other);
+        buf.extend_from_slice(&self.st_shndx.to_le_bytes());
+    }
+}
+
 #[allow(dead_code)]
 struct Elf32Rel {
     r_offset: Elf32Addr,
@@ -295,6 +336,17 @@ impl fmt::Display for Elf32Phdr {
 }
 
 impl Elf32Phdr {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.p_type.to_le_bytes());
+        buf.extend_from_slice(&self.p_offset.to_le_bytes());
+        buf.extend_from_slice(&self.p_vaddr.to_le_bytes());
+        buf.extend_from_slice(&self.p_paddr.to_le_bytes());
+        buf.extend_from_slice(&self.p_filesz.to_le_bytes());
+        buf.extend_from_slice(&self.p_memsz.to_le_bytes());
+        buf.extend_from_slice(&self.p_flags.to_le_bytes());
+        buf.extend_from_slice(&self.p_align.to_le_bytes());
+    }
+
     pub fn new() -> Elf32Phdr {
         Elf32Phdr {
             p_type: 0,
@@ -309,6 +361,13 @@ impl Elf32Phdr {
     }
 }
 
+/* Allocated section or sized symbol, at its load address */
+pub struct Elf32Name {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32
+}
+
 pub struct Elf32 {
     pub ehdr: Elf32Ehdr,
     pub phdrs: Vec<Elf32Phdr>,
@@ -490,9 +549,206 @@ impl Elf32 {
         }
     }
 
+    fn str_at(table: &[u8], offset: usize) -> String {
+        let bytes = table.get(offset..).unwrap_or(&[]);
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..len]).into_owned()
+    }
+
+    /* Run addresses of initialised data differ from where the segment is loaded */
+    fn load_addr(&self, vaddr: u32) -> u32 {
+        for phdr in &self.phdrs {
+            if phdr.p_type == 1 && vaddr >= phdr.p_vaddr && (vaddr - phdr.p_vaddr) < phdr.p_memsz {
+                return vaddr - phdr.p_vaddr + phdr.p_paddr;
+            }
+        }
+        vaddr
+    }
+
+    pub fn sections(&self) -> Vec<Elf32Name> {
+        let mut sections: Vec<Elf32Name> = Vec::new();
+        for shdr in &self.shdrs {
+            /* SHF_ALLOC, but not SHT_NOBITS */
+            if shdr.sh_flags & 0x2 == 0 || shdr.sh_type == 8 || shdr.sh_size == 0 {
+                continue;
+            }
+            sections.push(Elf32Name {
+                name: Elf32::str_at(&self.str_tab, shdr.sh_name as usize),
+                addr: self.load_addr(shdr.sh_addr),
+                size: shdr.sh_size
+            });
+        }
+        sections
+    }
+
+    /* Function and object symbols with a size, Thumb bits cleared */
+    pub fn symbols(&self, data: &[u8]) -> Vec<Elf32Name> {
+        let mut symbols: Vec<Elf32Name> = Vec::new();
+        for shdr in &self.shdrs {
+            if shdr.sh_type != 2 {
+                continue;
+            }
+            let str_tab: &[u8] = match self.shdrs.get(shdr.sh_link as usize) {
+                Some(s) => data.get(s.sh_offset as usize..(s.sh_offset + s.sh_size) as usize).unwrap_or(&[]),
+                None => &[],
+            };
+            let start = shdr.sh_offset as usize;
+            let end = usize::min(start + shdr.sh_size as usize, data.len());
+            let mut i = start;
+            while i + 16 <= end {
+                let st_name = slice_to_u32(&data[i..i+4]);
+                let st_value = slice_to_u32(&data[i+4..i+8]);
+                let st_size = slice_to_u32(&data[i+8..i+12]);
+                let st_type = data[i+12] & 0xf;
+                i += 16;
+
+                if (st_type != 1 && st_type != 2) || st_size == 0 {
+                    continue;
+                }
+                let addr = match (st_type, self.ehdr.e_machine) {
+                    (2, 40) => st_value & !1,
+                    _ => st_value,
+                };
+                symbols.push(Elf32Name {
+                    name: Elf32::str_at(str_tab, st_name as usize),
+                    addr: self.load_addr(addr),
+                    size: st_size
+                });
+            }
+        }
+        symbols
+    }
+
     pub fn parse_elf(&mut self, data: &[u8]) {
         self.parse_ehdr(data);
         self.parse_phdrs(data);
         self.parse_shdrs(data);
     }
+
+    /* Executable with one PT_LOAD segment and one section per (addr, data) pair.
+     * ARM images get `$t` mapping symbols so disassemblers decode Thumb code. */
+    pub fn write(e_machine: u16, e_entry: u32, segments: &[(u32, &[u8])], buf: &mut Vec<u8>) {
+        let thumb: bool = e_machine == 40;
+        let n = segments.len();
+
+        let mut shstr_tab: Vec<u8> = vec![0];
+        let mut section_names: Vec<u32> = Vec::new();
+        for i in 0..n {
+            section_names.push(shstr_tab.len() as u32);
+            shstr_tab.extend_from_slice(format!(".load{}\0", i).as_bytes());
+        }
+        let symtab_name = shstr_tab.len() as u32;
+        shstr_tab.extend_from_slice(b".symtab\0");
+        let strtab_name = shstr_tab.len() as u32;
+        shstr_tab.extend_from_slice(b".strtab\0");
+        let shstrtab_name = shstr_tab.len() as u32;
+        shstr_tab.extend_from_slice(b".shstrtab\0");
+        let str_tab: &[u8] = b"\0$t\0";
+
+        let mut symbols: Vec<Elf32Sym> = vec![Elf32Sym {
+            st_name: 0, st_value: 0, st_size: 0, st_info: 0, st_other: 0, st_shndx: 0
+        }];
+        if thumb {
+            for (i, (addr, _)) in segments.iter().enumerate() {
+                symbols.push(Elf32Sym {
+                    st_name: 1, st_value: *addr, st_size: 0, st_info: 0, st_other: 0, st_shndx: (i + 1) as u16
+                });
+            }
+        }
+
+        /* Layout: headers, segment data, symbol and string tables, section headers */
+        let mut offset: usize = 52 + 32*n;
+        let mut data_offsets: Vec<usize> = Vec::new();
+        for (_, data) in segments {
+            offset = offset.next_multiple_of(4);
+            data_offsets.push(offset);
+            offset += data.len();
+        }
+        let symtab_offset = offset.next_multiple_of(4);
+        let strtab_offset = symtab_offset + 16*symbols.len();
+        let shstrtab_offset = strtab_offset + str_tab.len();
+        let shoff = (shstrtab_offset + shstr_tab.len()).next_multiple_of(4);
+
+        let mut shdrs: Vec<Elf32Shdr> = vec![Elf32Shdr::new()];
+        for (i, (addr, data)) in segments.iter().enumerate() {
+            let mut shdr = Elf32Shdr::new();
+            shdr.sh_name = section_names[i];
+            shdr.sh_type = 1; /* SHT_PROGBITS */
+            shdr.sh_flags = 0x7; /* SHF_WRITE | SHF_ALLOC | SHF_EXECINSTR */
+            shdr.sh_addr = *addr;
+            shdr.sh_offset = data_offsets[i] as u32;
+            shdr.sh_size = data.len() as u32;
+            shdr.sh_addralign = 4;
+            shdrs.push(shdr);
+        }
+        let mut shdr = Elf32Shdr::new();
+        shdr.sh_name = symtab_name;
+        shdr.sh_type = 2; /* SHT_SYMTAB */
+        shdr.sh_offset = symtab_offset as u32;
+        shdr.sh_size = (16*symbols.len()) as u32;
+        shdr.sh_link = (n + 2) as u32;
+        shdr.sh_info = symbols.len() as u32;
+        shdr.sh_addralign = 4;
+        shdr.sh_entsize = 16;
+        shdrs.push(shdr);
+        let mut shdr = Elf32Shdr::new();
+        shdr.sh_name = strtab_name;
+        shdr.sh_type = 3; /* SHT_STRTAB */
+        shdr.sh_offset = strtab_offset as u32;
+        shdr.sh_size = str_tab.len() as u32;
+        shdr.sh_addralign = 1;
+        shdrs.push(shdr);
+        let mut shdr = Elf32Shdr::new();
+        shdr.sh_name = shstrtab_name;
+        shdr.sh_type = 3; /* SHT_STRTAB */
+        shdr.sh_offset = shstrtab_offset as u32;
+        shdr.sh_size = shstr_tab.len() as u32;
+        shdr.sh_addralign = 1;
+        shdrs.push(shdr);
+
+        let mut ehdr = Elf32Ehdr::new();
+        ehdr.e_ident[..7].copy_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1]);
+        ehdr.e_type = 2; /* ET_EXEC */
+        ehdr.e_machine = e_machine;
+        ehdr.e_version = 1;
+        ehdr.e_entry = e_entry;
+        ehdr.e_phoff = 52;
+        ehdr.e_shoff = shoff as u32;
+        ehdr.e_flags = if thumb { 0x05000000 } else { 0 }; /* ARM EABI version 5 */
+        ehdr.e_ehsize = 52;
+        ehdr.e_phentsize = 32;
+        ehdr.e_phnum = n as u16;
+        ehdr.e_shentsize = 40;
+        ehdr.e_shnum = shdrs.len() as u16;
+        ehdr.e_shstrndx = (shdrs.len() - 1) as u16;
+
+        let start = buf.len();
+        ehdr.write(buf);
+        for (i, (addr, data)) in segments.iter().enumerate() {
+            let mut phdr = Elf32Phdr::new();
+            phdr.p_type = 1; /* PT_LOAD */
+            phdr.p_offset = data_offsets[i] as u32;
+            phdr.p_vaddr = *addr;
+            phdr.p_paddr = *addr;
+            phdr.p_filesz = data.len() as u32;
+            phdr.p_memsz = data.len() as u32;
+            phdr.p_flags = 0x7; /* PF_R | PF_W | PF_X */
+            phdr.p_align = 4;
+            phdr.write(buf);
+        }
+        for (i, (_, data)) in segments.iter().enumerate() {
+            buf.resize(start + data_offsets[i], 0);
+            buf.extend_from_slice(data);
+        }
+        buf.resize(start + symtab_offset, 0);
+        for symbol in &symbols {
+            symbol.write(buf);
+        }
+        buf.extend_from_slice(str_tab);
+        buf.extend_from_slice(&shstr_tab);
+        buf.resize(start + shoff, 0);
+        for shdr in &shdrs {
+            shdr.write(buf);
+        }
+    }
 }
@@ -1,79 +1,404 @@
+use std::collections::HashSet;
+
+use crate::bin::MAX_BIN_SIZE;
 use crate::elf::*;
+use crate::image::MemoryImage;
+use crate::target::Target;
 use crate::uf2::*;
 
-const HAS_FAMILY_ID: u32    = 0x00002000;
-const MAGIC_START0: u32     = 0x0a324655;
-const MAGIC_START1: u32     = 0x9e5d5157;
-const MAGIC_END: u32        = 0x0ab16f30;
 const PAYLOAD_SIZE: u32     = 256;
+const MAX_PAYLOAD_SIZE: u32 = 476;
+const DATA_FAMILY_ID: u32   = 0xe48bff58;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BlockOrder {
+    /* Program header order, as blocks are generated */
+    Elf,
+    Address,
+    /* Pages holding the vector table or boot stage first, then by address */
+    BootFirst
+}
+
+/* Sections the bootrom reads before anything else */
+const BOOT_SECTIONS: &[&str] = &[".boot2", ".vectors", ".vector_table", ".isr_vector"];
 
 pub struct Elf2Uf2 {
     elf: Elf32,
-    pub uf2: Uf2
+    pub uf2: Uf2,
+    pub extensions: Vec<Uf2Extension>,
+    pub md5: bool,
+    pub payload_size: u32,
+    pub family_id: Option<u32>,
+    /* Address ranges [start, end) whose blocks the bootloader must not write to main flash */
+    pub not_main_flash: Vec<(u32, u32)>,
+    pub metadata: Vec<(u32, Vec<u8>)>,
+    /* Image already on the device, only pages that differ from it are written */
+    pub baseline: Option<MemoryImage>,
+    /* Each input is made contiguous, padded to `align` and to `total_size` bytes with `fill` */
+    pub fill_gaps: bool,
+    pub align: Option<u32>,
+    pub total_size: Option<u32>,
+    pub fill: u8,
+    /* Largest input `fill_gaps` may produce */
+    pub max_size: u64,
+    pub order: BlockOrder,
+    /* Added to every address by convert() and convert_image(), modulo 2^32 */
+    pub offset: u32,
+    /* Address ranges [start, end), such as a bootloader, that no block may touch */
+    pub protected: Vec<(u32, u32)>,
+    target: Option<Target>,
+    /* Only the last block for a page is kept, that is the one the bootloader leaves in flash */
+    pub dedup: bool,
+    /* Address ranges [start, end) of boot-critical data, per input */
+    boot_ranges: Vec<(u32, u32)>,
+    /* Index of the first block of every input added so far */
+    inputs: Vec<usize>
+}
+
+pub fn default_family_id(e_machine: u16) -> u32 {
+    match e_machine {
+        40      => 0xe48bff59, /* ARM */
+        243     => 0xe48bff5a, /* RISCV */
+        _       => DATA_FAMILY_ID, /* generic catch-all data */
+    }
+}
+
+fn new_block(target_addr: u32, payload_size: u32, family_id: u32) -> Uf2Block {
+    let mut block = Uf2Block::new();
+    block.magic_start0 = MAGIC_START0;
+    block.magic_start1 = MAGIC_START1;
+    block.flags |= HAS_FAMILY_ID;
+    block.target_addr = target_addr;
+    block.payload_size = payload_size;
+    block.family_id = family_id;
+    block.magic_end = MAGIC_END;
+    block
+}
+
+/* Bytes outside the baseline image count as erased to zero padding */
+fn is_unchanged(baseline: &MemoryImage, block: &Uf2Block) -> bool {
+    let mut present = false;
+    for (i, byte) in block.payload().iter().enumerate() {
+        match baseline.read(block.target_addr.wrapping_add(i as u32), 1) {
+            Some(b) if b[0] == *byte => present = true,
+            None if *byte == 0 => {},
+            _ => return false,
+        }
+    }
+    present
+}
+
+/* Families whose bootloaders ignore blocks that do not carry exactly 256 bytes */
+const FIXED_PAYLOAD_FAMILIES: &[u32] = &[
+    0x68ed2b88, /* SAMD21 */
+    0x55114460, /* SAMD51 */
+    0x1851780a, /* SAML21 */
+    0x1b57745f, /* nRF52 */
+    0x621e937a, /* nRF52833 */
+    0xada52840, /* nRF52840 */
+];
+
+fn check_payload_size(payload_size: u32, family_id: u32, target: Option<&Target>) -> Result<(), String> {
+    if payload_size == 0 || payload_size > MAX_PAYLOAD_SIZE || !payload_size.is_multiple_of(4) {
+        return Err(format!("Payload size {} must be a multiple of 4 between 4 and {}",
+                           payload_size, MAX_PAYLOAD_SIZE));
+    }
+    /* RP2040 and RP2350 bootroms are just as strict */
+    let fixed = (0xe48bff56..=0xe48bff5b).contains(&family_id) || FIXED_PAYLOAD_FAMILIES.contains(&family_id);
+    if fixed && payload_size != 256 {
+        return Err(format!("Family {:#x} requires a payload size of 256, got {}",
+                           family_id, payload_size));
+    }
+    if let Some(target) = target.filter(|t| t.page_size != payload_size) {
+        return Err(format!("Target {} requires a payload size of {}, got {}",
+                           target.name, target.page_size, payload_size));
+    }
+    Ok(())
 }
 
 impl Elf2Uf2 {
     pub fn new() -> Elf2Uf2 {
         Elf2Uf2 {
             elf: Elf32::new(),
-            uf2: Uf2::new()
+            uf2: Uf2::new(),
+            extensions: Vec::new(),
+            md5: false,
+            payload_size: PAYLOAD_SIZE,
+            family_id: None,
+            not_main_flash: Vec::new(),
+            metadata: Vec::new(),
+            baseline: None,
+            fill_gaps: false,
+            align: None,
+            total_size: None,
+            fill: 0,
+            max_size: MAX_BIN_SIZE,
+            order: BlockOrder::Elf,
+            offset: 0,
+            protected: Vec::new(),
+            target: None,
+            dedup: false,
+            boot_ranges: Vec::new(),
+            inputs: Vec::new()
         }
     }
 
-    pub fn convert(&mut self, data: &[u8], buf: &mut Vec<u8>) {
+    /* Payload size from the target's page size, its reserved ranges are protected */
+    pub fn set_target(&mut self, target: Target) {
+        self.payload_size = target.page_size;
+        self.protected.extend_from_slice(&target.reserved);
+        self.target = Some(target);
+    }
+
+    pub fn convert(&mut self, data: &[u8], buf: &mut Vec<u8>) -> Result<(), String> {
+        self.add_elf(data, self.offset, None)?;
+        self.write(buf)
+    }
+
+    /* Blocks for a memory image from a non-ELF input, pages shared by two regions are merged */
+    pub fn convert_image(&mut self, image: &MemoryImage, buf: &mut Vec<u8>) -> Result<(), String> {
+        self.add_image(image, self.offset, None)?;
+        self.write(buf)
+    }
+
+    /* Adds the loadable segments of an ELF moved by `offset`, `family_id` overrides self.family_id */
+    pub fn add_elf(&mut self, data: &[u8], offset: u32, family_id: Option<u32>) -> Result<(), String> {
+        self.elf = Elf32::new();
         self.elf.parse_elf(data);
 
-        let family_id: u32 = match self.elf.ehdr.e_machine {
-            40      => 0xe48bff59, /* ARM */
-            243     => 0xe48bff5a, /* RISCV */
-            _       => 0xe48bff58, /* generic catch-all data */
-        };
+        let machine_family_id = default_family_id(self.elf.ehdr.e_machine);
+        let family_id: u32 = family_id.or(self.family_id)
+            .unwrap_or(match &self.target {
+                Some(target) => target.default_family_id(machine_family_id),
+                None => machine_family_id,
+            });
 
-        for phdr in &self.elf.phdrs {
-            if phdr.p_type != 1 {
-                continue;
-            }
-
-            let file_size: u32 = u32::min(phdr.p_memsz, phdr.p_filesz);
-            if file_size == 0 {
-                continue;
-            }
-
-            let n: usize = ((file_size + PAYLOAD_SIZE - 1) / PAYLOAD_SIZE) as usize;
-            let i: usize = phdr.p_offset as usize;
-            let mut j: usize = 0;
-            let mut start_addr: u32 = phdr.p_paddr & !0xff;
-            let mut k: usize = (phdr.p_paddr - start_addr) as usize;
-            while j < n {
-                let mut block = Uf2Block::new();
-                block.magic_start0 = MAGIC_START0;
-                block.magic_start1 = MAGIC_START1;
-                block.flags |= HAS_FAMILY_ID;
-                block.target_addr = start_addr;
-                block.payload_size = PAYLOAD_SIZE;
-                block.family_id = family_id;
-                while k < PAYLOAD_SIZE as usize {
-                    if j*(PAYLOAD_SIZE as usize)+k < (file_size as usize) {
-                        block.data[k] = data[i+j*(PAYLOAD_SIZE as usize)+k];
-                    }
-                    k += 1;
+        let mut boot_ranges: Vec<(u32, u32)> = self.elf.sections().iter()
+            .filter(|s| BOOT_SECTIONS.contains(&s.name.as_str()))
+            .map(|s| (s.addr.wrapping_add(offset), s.addr.wrapping_add(offset).saturating_add(s.size)))
+            .collect();
+        if boot_ranges.is_empty() {
+            let image = MemoryImage::from_elf(&self.elf, data);
+            if let Some(first) = image.regions.first() {
+                let addr = first.addr.wrapping_add(offset);
+                boot_ranges.push((addr, addr.saturating_add(1)));
+            }
+        }
+        self.boot_ranges.extend(boot_ranges);
+
+        /* Pages shared by two segments become one block, blocks follow the program headers */
+        let first_block = self.uf2.blocks.len();
+        let image = MemoryImage::from_elf(&self.elf, data);
+        self.add_image(&image, offset, Some(family_id))?;
+
+        let segments: Vec<(u64, u64)> = self.elf.phdrs.iter()
+            .filter(|p| p.p_type == 1 && u32::min(p.p_memsz, p.p_filesz) > 0)
+            .map(|p| {
+                let start = p.p_paddr.wrapping_add(offset) as u64;
+                (start, start + u32::min(p.p_memsz, p.p_filesz) as u64)
+            })
+            .collect();
+        let mut segment = 0;
+        let mut keys: Vec<usize> = Vec::new();
+        for block in &self.uf2.blocks[first_block..] {
+            let start = block.target_addr as u64;
+            let end = start + block.payload_size as u64;
+            /* Padding between and after segments stays with the block before it */
+            if let Some(i) = segments.iter().position(|&(s, e)| start < e && s < end) {
+                segment = i;
+            }
+            keys.push(segment);
+        }
+        let mut keyed: Vec<(usize, Uf2Block)> = keys.into_iter().zip(self.uf2.blocks.drain(first_block..)).collect();
+        keyed.sort_by_key(|(key, _)| *key);
+        self.uf2.blocks.extend(keyed.into_iter().map(|(_, block)| block));
+        Ok(())
+    }
+
+    pub fn add_image(&mut self, image: &MemoryImage, offset: u32, family_id: Option<u32>) -> Result<(), String> {
+        let family_id: u32 = family_id.or(self.family_id)
+            .unwrap_or(match &self.target {
+                Some(target) => target.default_family_id(DATA_FAMILY_ID),
+                None => DATA_FAMILY_ID,
+            });
+        let payload_size: u32 = self.payload_size;
+        check_payload_size(payload_size, family_id, self.target.as_ref())?;
+        let mut image = image.offset(offset)?;
+        if self.fill_gaps {
+            image.fill_gaps(self.fill, self.max_size)?;
+        }
+        if let Some(align) = self.align {
+            image.pad(align, self.fill)?;
+        }
+        if let Some(total_size) = self.total_size {
+            image.pad_to_size(total_size, self.fill)?;
+        }
+
+        let first_block = self.uf2.blocks.len();
+        for region in &image.regions {
+            let mut addr: u64 = region.addr as u64;
+            while addr < region.end() {
+                let page: u64 = addr - addr % payload_size as u64;
+                let page_end: u64 = u64::min(page + payload_size as u64, region.end());
+                let is_last_page = self.uf2.blocks.len() > first_block
+                    && self.uf2.blocks.last().map(|b| b.target_addr as u64) == Some(page);
+                if !is_last_page {
+                    self.uf2.blocks.push(new_block(page as u32, payload_size, family_id));
                 }
-                block.magic_end = MAGIC_END;
-                start_addr += PAYLOAD_SIZE;
-                j += 1;
-                k = 0;
-                self.uf2.blocks.push(block);
+                let block = self.uf2.blocks.last_mut().unwrap();
+                let i = (addr - region.addr as u64) as usize;
+                let k = (addr - page) as usize;
+                let len = (page_end - addr) as usize;
+                block.data[k..k+len].copy_from_slice(&region.data[i..i+len]);
+                addr = page_end;
             }
         }
 
-        self.uf2.write(buf);
+        self.check_overlap(first_block)
+    }
+
+    /* Blocks of the input starting at `first_block` must not rewrite pages of earlier inputs */
+    /* Blocks sorted by family and address, each is checked against the furthest reaching block
+       before it from the other side, earlier inputs or this one */
+    fn check_overlap(&mut self, first_block: usize) -> Result<(), String> {
+        let blocks = &self.uf2.blocks;
+        let end = |i: usize| blocks[i].target_addr as u64 + blocks[i].payload_size as u64;
+        let mut order: Vec<usize> = (0..blocks.len()).collect();
+        order.sort_by_key(|&i| (blocks[i].family_id, blocks[i].target_addr));
+
+        let mut family: Option<u32> = None;
+        let (mut earlier, mut current): (Option<usize>, Option<usize>) = (None, None);
+        for i in order {
+            if family != Some(blocks[i].family_id) {
+                family = Some(blocks[i].family_id);
+                (earlier, current) = (None, None);
+            }
+            let (reach, other) = match i < first_block {
+                true => (&mut earlier, current),
+                false => (&mut current, earlier),
+            };
+            if let Some(o) = other.filter(|&o| end(o) > blocks[i].target_addr as u64) {
+                let (old, new) = (usize::min(i, o), usize::max(i, o));
+                let start = u64::max(blocks[old].target_addr as u64, blocks[new].target_addr as u64);
+                let message = format!("Input {} overlaps input {} at {:#010x}..{:#010x} (family {:#x})",
+                                      self.inputs.len() + 1, self.inputs.partition_point(|&s| s <= old),
+                                      start, u64::min(end(old), end(new)), blocks[i].family_id);
+                self.uf2.blocks.truncate(first_block);
+                return Err(message);
+            }
+            if reach.is_none_or(|r| end(r) < end(i)) {
+                *reach = Some(i);
+            }
+        }
+        self.inputs.push(first_block);
+        Ok(())
+    }
+
+    /* Metadata and trailers are added once for all inputs, then blocks are numbered,
+       all on a copy of the blocks so that writing twice gives the same file */
+    pub fn write(&self, buf: &mut Vec<u8>) -> Result<(), String> {
+        let mut uf2 = self.uf2.clone();
+        let family_id: u32 = uf2.blocks.first().map(|b| b.family_id)
+            .or(self.family_id).unwrap_or(DATA_FAMILY_ID);
+        let payload_size: u32 = self.payload_size;
+
+        for block in &uf2.blocks {
+            let start = block.target_addr as u64;
+            let end = start + block.payload_size as u64;
+            if let Some(target) = &self.target {
+                if block.flags & NOT_MAIN_FLASH == 0 && !target.contains(block.target_addr, end) {
+                    return Err(format!("Block at {:#010x}..{:#010x} is outside the flash and RAM of {}",
+                                       start, end, target.name));
+                }
+            }
+            if let Some(&(s, e)) = self.protected.iter().find(|&&(s, e)| start < e as u64 && (s as u64) < end) {
+                return Err(format!("Block at {:#010x}..{:#010x} overlaps protected region {:#010x}..{:#010x}",
+                                   start, end, s, e));
+            }
+        }
+
+        if self.dedup {
+            let mut seen: HashSet<(u32, u32, u32)> = HashSet::new();
+            let mut blocks: Vec<Uf2Block> = Vec::new();
+            for block in uf2.blocks.drain(..).rev() {
+                if seen.insert((block.target_addr, block.family_id, block.flags & NOT_MAIN_FLASH)) {
+                    blocks.push(block);
+                }
+            }
+            blocks.reverse();
+            uf2.blocks = blocks;
+        }
+
+        match self.order {
+            BlockOrder::Elf => {},
+            BlockOrder::Address => uf2.blocks.sort_by_key(|b| (b.family_id, b.target_addr)),
+            BlockOrder::BootFirst => {
+                let boot_ranges = &self.boot_ranges;
+                uf2.blocks.sort_by_key(|b| {
+                    let start = b.target_addr as u64;
+                    let end = start + b.payload_size as u64;
+                    let boot = boot_ranges.iter().any(|&(s, e)| start < e as u64 && (s as u64) < end);
+                    (!boot, b.family_id, b.target_addr)
+                });
+            },
+        }
+
+        if let Some(baseline) = &self.baseline {
+            /* The last block of a page decides what ends up in flash, all blocks of it stay together */
+            let mut seen: HashSet<(u32, u32)> = HashSet::new();
+            let mut changed: HashSet<(u32, u32)> = HashSet::new();
+            for block in uf2.blocks.iter().rev() {
+                let page = (block.target_addr, block.family_id);
+                if seen.insert(page) && !is_unchanged(baseline, block) {
+                    changed.insert(page);
+                }
+            }
+            uf2.blocks.retain(|b| changed.contains(&(b.target_addr, b.family_id)));
+            if uf2.blocks.is_empty() {
+                return Err("No pages differ from the baseline".to_string());
+            }
+        }
+
+        for (addr, metadata) in &self.metadata {
+            if *addr as u64 + metadata.len() as u64 > 1 << 32 {
+                return Err(format!("Metadata at {:#010x} of {} bytes runs past the end of memory", addr, metadata.len()));
+            }
+            for (j, chunk) in metadata.chunks(payload_size as usize).enumerate() {
+                let mut block = new_block(addr + j as u32 * payload_size, chunk.len() as u32, family_id);
+                block.flags |= NOT_MAIN_FLASH;
+                block.data[..chunk.len()].copy_from_slice(chunk);
+                uf2.blocks.push(block);
+            }
+        }
+
+        for block in &mut uf2.blocks {
+            let start = block.target_addr as u64;
+            let end = start + block.payload_size as u64;
+            if self.not_main_flash.iter().any(|&(s, e)| start < e as u64 && (s as u64) < end) {
+                block.flags |= NOT_MAIN_FLASH;
+            }
+        }
+
+        if !self.extensions.is_empty() {
+            uf2.set_extensions(&self.extensions)?;
+        }
+        if self.md5 {
+            uf2.set_md5()?;
+        }
+
+        uf2.write(buf);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs;
-    use crate::elf2uf2::Elf2Uf2;
+    use crate::elf::Elf32;
+    use crate::elf2uf2::{BlockOrder, Elf2Uf2};
+    use crate::target::Target;
+    use crate::image::MemoryImage;
+    use crate::uf2::{Uf2, NOT_MAIN_FLASH};
 
     #[test]
     fn test_elf2uf2() {
@@ -89,7 +414,7 @@ mod tests {
                             .expect("Should be a uf2 file given as input");
             let mut buf = Vec::<u8>::new();
             let mut elf2uf2 = Elf2Uf2::new();
-            elf2uf2.convert(&data, &mut buf);
+            elf2uf2.convert(&data, &mut buf).unwrap();
 
             assert_eq!(buf.len(), correct.len());
             for (b1, b2) in buf.iter().zip(correct.iter()) {
@@ -97,4 +422,309 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_segment_bytes() {
+        /* Checked against the program headers directly, the fixtures above are this converter's output */
+        for elf_name in ["test/test_arm.elf", "test/test_riscv.elf"] {
+            let data: Vec<u8> = fs::read(elf_name)
+                            .expect("Should be a elf file given as input");
+            let mut buf = Vec::<u8>::new();
+            Elf2Uf2::new().convert(&data, &mut buf).unwrap();
+            let uf2 = Uf2::parse(&buf).unwrap();
+
+            let mut elf = Elf32::new();
+            elf.parse_elf(&data);
+            let mut pages: Vec<u32> = Vec::new();
+            for phdr in elf.phdrs.iter().filter(|p| p.p_type == 1) {
+                for k in 0..u32::min(phdr.p_memsz, phdr.p_filesz) {
+                    let addr = phdr.p_paddr + k;
+                    let blocks: Vec<_> = uf2.blocks.iter()
+                        .filter(|b| b.target_addr <= addr && addr - b.target_addr < b.payload_size)
+                        .collect();
+                    assert_eq!(blocks.len(), 1, "{}: {:#x}", elf_name, addr);
+                    assert_eq!(blocks[0].data[(addr - blocks[0].target_addr) as usize],
+                               data[(phdr.p_offset + k) as usize], "{}: {:#x}", elf_name, addr);
+                    if !pages.contains(&blocks[0].target_addr) {
+                        pages.push(blocks[0].target_addr);
+                    }
+                }
+            }
+            assert_eq!(uf2.blocks.len(), pages.len());
+        }
+    }
+
+    #[test]
+    fn test_payload_size() {
+        let data: Vec<u8> = fs::read("test/test_arm.elf")
+                        .expect("Should be a elf file given as input");
+
+        let mut buf = Vec::<u8>::new();
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.payload_size = 476;
+        assert!(elf2uf2.convert(&data, &mut buf).is_err());
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.family_id = Some(0x68ed2b88); /* SAMD21 */
+        elf2uf2.payload_size = 476;
+        assert!(elf2uf2.convert(&data, &mut buf).is_err());
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.set_target(Target::find("rp2350", &[]).unwrap());
+        elf2uf2.family_id = Some(0x12345678);
+        elf2uf2.payload_size = 476;
+        assert!(elf2uf2.convert(&data, &mut buf).is_err());
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.family_id = Some(0x12345678); /* a custom bootloader */
+        elf2uf2.payload_size = 476;
+        elf2uf2.convert(&data, &mut buf).unwrap();
+        let uf2 = Uf2::parse(&buf).unwrap();
+        for block in &uf2.blocks {
+            assert_eq!(block.payload_size, 476);
+            assert_eq!(block.family_id, 0x12345678);
+            assert_eq!(block.target_addr % 476, 0);
+        }
+        let mut elf = Elf32::new();
+        elf.parse_elf(&data);
+        let expected = MemoryImage::from_elf(&elf, &data);
+        let image = MemoryImage::from_uf2(&uf2);
+        for region in &expected.regions {
+            assert_eq!(image.read(region.addr, region.data.len()), Some(region.data.as_slice()));
+        }
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.family_id = Some(0x12345678);
+        elf2uf2.payload_size = 478;
+        assert!(elf2uf2.convert(&data, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_convert_image() {
+        let mut image = MemoryImage::new();
+        image.insert(0x1080, &[1; 0x100]);
+        image.insert(0x1190, &[2; 0x10]);
+        image.insert(0x2000, &[3; 4]);
+
+        let mut buf = Vec::<u8>::new();
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.convert_image(&image, &mut buf).unwrap();
+        let uf2 = Uf2::parse(&buf).unwrap();
+        let addrs: Vec<u32> = uf2.blocks.iter().map(|b| b.target_addr).collect();
+        assert_eq!(addrs, vec![0x1000, 0x1100, 0x2000]);
+        assert_eq!(uf2.blocks[0].family_id, 0xe48bff58);
+        assert_eq!(uf2.blocks[1].data[0x7f], 1);
+        assert_eq!(uf2.blocks[1].data[0x80], 0);
+        assert_eq!(uf2.blocks[1].data[0x90], 2);
+
+        let parsed = MemoryImage::from_uf2(&uf2);
+        assert_eq!(parsed.read(0x1080, 0x100), image.read(0x1080, 0x100));
+        assert_eq!(parsed.read(0x1190, 0x10), image.read(0x1190, 0x10));
+    }
+
+    #[test]
+    fn test_not_main_flash() {
+        let data: Vec<u8> = fs::read("test/test_arm.elf")
+                        .expect("Should be a elf file given as input");
+        let mut buf = Vec::<u8>::new();
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.not_main_flash.push((0x10000100, 0x10000201));
+        elf2uf2.metadata.push((0x30000000, b"built by ci".to_vec()));
+        elf2uf2.convert(&data, &mut buf).unwrap();
+
+        let uf2 = Uf2::parse(&buf).unwrap();
+        let marked: Vec<u32> = uf2.blocks.iter()
+            .filter(|b| b.flags & NOT_MAIN_FLASH != 0)
+            .map(|b| b.target_addr).collect();
+        assert_eq!(marked, vec![0x10000100, 0x10000200, 0x30000000]);
+        assert_eq!(uf2.blocks.last().unwrap().payload(), b"built by ci");
+
+        /* Writing again gives the same file, metadata is not added twice */
+        let mut again = Vec::<u8>::new();
+        elf2uf2.write(&mut again).unwrap();
+        assert!(again == buf);
+
+        elf2uf2.metadata.push((0xffffff00, vec![0; 0x101]));
+        assert!(elf2uf2.write(&mut again).is_err());
+        elf2uf2.metadata.last_mut().unwrap().1.pop();
+        assert!(elf2uf2.write(&mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn test_merge() {
+        let data: Vec<u8> = fs::read("test/test_arm.elf")
+                        .expect("Should be a elf file given as input");
+        let mut blob = MemoryImage::new();
+        blob.insert(0x100, &[7; 0x180]);
+
+        let mut buf = Vec::<u8>::new();
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.add_elf(&data, 0, None).unwrap();
+        elf2uf2.add_elf(&data, 0x10000, None).unwrap();
+        elf2uf2.add_elf(&data, 0, Some(0xe48bff5a)).unwrap();
+        elf2uf2.add_image(&blob, 0x10100000, Some(0xe48bff59)).unwrap();
+        assert_eq!(elf2uf2.add_elf(&data, 0x1000, None).err(),
+                   Some("Input 5 overlaps input 1 at 0x10001000..0x10001100 (family 0xe48bff59)".to_string()));
+        assert!(elf2uf2.add_image(&blob, 0x10001500, Some(0xe48bff59)).is_err());
+        elf2uf2.write(&mut buf).unwrap();
+
+        let uf2 = Uf2::parse(&buf).unwrap();
+        assert_eq!(uf2.blocks.len(), 3 * 23 + 2);
+        for (i, block) in uf2.blocks.iter().enumerate() {
+            assert_eq!(block.block_no, i as u32);
+            assert_eq!(block.num_blocks, 71);
+        }
+        assert_eq!(uf2.blocks[23].target_addr, 0x10010000);
+        assert_eq!(uf2.blocks[46].family_id, 0xe48bff5a);
+        assert_eq!(uf2.blocks[70].target_addr, 0x10100200);
+        assert_eq!(uf2.blocks[70].data[0x7f], 7);
+    }
+
+    #[test]
+    fn test_baseline() {
+        let data: Vec<u8> = fs::read("test/test_arm.elf")
+                        .expect("Should be a elf file given as input");
+        let mut buf = Vec::<u8>::new();
+        Elf2Uf2::new().convert(&data, &mut buf).unwrap();
+        let mut baseline = MemoryImage::from_uf2(&Uf2::parse(&buf).unwrap());
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.baseline = Some(MemoryImage::from_uf2(&Uf2::parse(&buf).unwrap()));
+        assert!(elf2uf2.convert(&data, &mut Vec::new()).is_err());
+
+        baseline.insert(0x10000210, &[0xff]);
+        baseline.insert(0x10001610, &[0xff]);
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.baseline = Some(baseline);
+        let mut buf = Vec::<u8>::new();
+        elf2uf2.convert(&data, &mut buf).unwrap();
+        let uf2 = Uf2::parse(&buf).unwrap();
+        let addrs: Vec<u32> = uf2.blocks.iter().map(|b| b.target_addr).collect();
+        assert_eq!(addrs, vec![0x10000200, 0x10001600]);
+        assert_eq!(uf2.blocks[1].num_blocks, 2);
+    }
+
+    #[test]
+    fn test_fill_and_pad() {
+        let data: Vec<u8> = fs::read("test/test_riscv.elf")
+                        .expect("Should be a elf file given as input");
+        let mut buf = Vec::<u8>::new();
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.fill_gaps = true;
+        elf2uf2.align = Some(0x1000);
+        elf2uf2.fill = 0xff;
+        elf2uf2.convert(&data, &mut buf).unwrap();
+
+        let uf2 = Uf2::parse(&buf).unwrap();
+        assert_eq!(uf2.blocks.len(), 32);
+        assert_eq!(uf2.blocks[31].target_addr, 0x10001f00);
+        assert_eq!(uf2.blocks[31].data[0], 0xff);
+        assert_eq!(uf2.blocks[0].family_id, 0xe48bff5a);
+        let mut elf = crate::elf::Elf32::new();
+        elf.parse_elf(&data);
+        let expected = MemoryImage::from_elf(&elf, &data);
+        assert_eq!(MemoryImage::from_uf2(&uf2).read(0x10000000, 0x1774), expected.read(0x10000000, 0x1774));
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.total_size = Some(0x1000);
+        assert!(elf2uf2.convert(&data, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_order_and_dedup() {
+        let mut elf = Vec::<u8>::new();
+        Elf32::write(40, 0x10000100, &[(0x10000300, &[3; 0x10]), (0x10000100, &[1; 0x100]), (0x10000000, &[0; 0x100])], &mut elf);
+
+        let addrs = |order: BlockOrder, dedup: bool| -> Vec<u32> {
+            let mut buf = Vec::<u8>::new();
+            let mut elf2uf2 = Elf2Uf2::new();
+            elf2uf2.order = order;
+            elf2uf2.dedup = dedup;
+            elf2uf2.add_elf(&elf, 0, None).unwrap();
+            elf2uf2.uf2.blocks.push(elf2uf2.uf2.blocks[0].clone());
+            elf2uf2.write(&mut buf).unwrap();
+            Uf2::parse(&buf).unwrap().blocks.iter().map(|b| b.target_addr).collect()
+        };
+        assert_eq!(addrs(BlockOrder::Elf, false), vec![0x10000300, 0x10000100, 0x10000000, 0x10000300]);
+        assert_eq!(addrs(BlockOrder::Elf, true), vec![0x10000100, 0x10000000, 0x10000300]);
+        assert_eq!(addrs(BlockOrder::Address, true), vec![0x10000000, 0x10000100, 0x10000300]);
+
+        let mut buf = Vec::<u8>::new();
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.order = BlockOrder::BootFirst;
+        elf2uf2.add_elf(&elf, 0, None).unwrap();
+        elf2uf2.boot_ranges = vec![(0x10000100, 0x10000200)];
+        elf2uf2.write(&mut buf).unwrap();
+        let uf2 = Uf2::parse(&buf).unwrap();
+        assert_eq!(uf2.blocks.iter().map(|b| b.target_addr).collect::<Vec<u32>>(), vec![0x10000100, 0x10000000, 0x10000300]);
+    }
+
+    #[test]
+    fn test_offset_and_protected() {
+        let mut elf = Vec::<u8>::new();
+        Elf32::write(40, 0, &[(0, &[1; 0x300])], &mut elf);
+
+        let mut buf = Vec::<u8>::new();
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.family_id = Some(0xada52840); /* nRF52840 */
+        elf2uf2.offset = 0x26000;
+        elf2uf2.protected.push((0, 0x26000));
+        elf2uf2.convert(&elf, &mut buf).unwrap();
+        let uf2 = Uf2::parse(&buf).unwrap();
+        assert_eq!(uf2.blocks.iter().map(|b| b.target_addr).collect::<Vec<u32>>(), vec![0x26000, 0x26100, 0x26200]);
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.offset = 0x25f00;
+        elf2uf2.protected.push((0, 0x26000));
+        assert!(elf2uf2.convert(&elf, &mut buf).is_err());
+
+        let mut image = MemoryImage::new();
+        image.insert(0x10000000, &[2; 4]);
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.offset = 0u32.wrapping_sub(0x10000000);
+        let mut buf = Vec::<u8>::new();
+        elf2uf2.convert_image(&image, &mut buf).unwrap();
+        assert_eq!(Uf2::parse(&buf).unwrap().blocks[0].target_addr, 0);
+
+        let mut top = Vec::<u8>::new();
+        Elf32::write(40, 0xffffff00, &[(0xffffff00, &[4; 0x100])], &mut top);
+        let mut buf = Vec::<u8>::new();
+        Elf2Uf2::new().convert(&top, &mut buf).unwrap();
+        assert_eq!(Uf2::parse(&buf).unwrap().blocks[0].target_addr, 0xffffff00);
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.offset = 0x80;
+        assert!(elf2uf2.convert(&top, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_target() {
+        let data: Vec<u8> = fs::read("test/test_arm.elf")
+                        .expect("Should be a elf file given as input");
+        let mut buf = Vec::<u8>::new();
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.set_target(Target::find("rp2040", &[]).unwrap());
+        elf2uf2.convert(&data, &mut buf).unwrap();
+        assert_eq!(Uf2::parse(&buf).unwrap().blocks[0].family_id, 0xe48bff56);
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.set_target(Target::find("rp2350", &[]).unwrap());
+        let mut buf = Vec::<u8>::new();
+        elf2uf2.convert(&data, &mut buf).unwrap();
+        assert_eq!(Uf2::parse(&buf).unwrap().blocks[0].family_id, 0xe48bff59);
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.set_target(Target::find("samd21", &[]).unwrap());
+        assert!(elf2uf2.convert(&data, &mut buf).is_err());
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.set_target(Target::find("nrf52840", &[]).unwrap());
+        elf2uf2.offset = 0u32.wrapping_sub(0x10000000);
+        assert!(elf2uf2.convert(&data, &mut buf).is_err());
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.set_target(Target::find("nrf52840", &[]).unwrap());
+        elf2uf2.offset = 0x26000u32.wrapping_sub(0x10000000);
+        let mut buf = Vec::<u8>::new();
+        elf2uf2.convert(&data, &mut buf).unwrap();
+        assert_eq!(Uf2::parse(&buf).unwrap().blocks[0].family_id, 0xada52840);
+    }
 }
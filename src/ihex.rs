@@ -0,0 +1,171 @@
+use crate::image::MemoryImage;
+
+const DATA: u8                      = 0x00;
+const END_OF_FILE: u8               = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8  = 0x02;
+const START_SEGMENT_ADDRESS: u8     = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8   = 0x04;
+const START_LINEAR_ADDRESS: u8      = 0x05;
+
+const RECORD_LEN: usize             = 16;
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg()
+}
+
+/* Two ASCII hex digits, anything else is rejected rather than sliced mid-character, also used for S-records */
+pub fn parse_hex_byte(digits: &[u8]) -> Result<u8, String> {
+    match std::str::from_utf8(digits) {
+        Ok(s) if digits.len() == 2 && digits.iter().all(|c| c.is_ascii_hexdigit()) => u8::from_str_radix(s, 16)
+            .map_err(|_| format!("invalid hex digits '{}'", s)),
+        _ => Err(format!("invalid hex digits '{}'", String::from_utf8_lossy(digits))),
+    }
+}
+
+fn parse_record(line: &str) -> Result<Vec<u8>, String> {
+    let hex = line.as_bytes().strip_prefix(b":").ok_or("record does not start with ':'".to_string())?;
+    if hex.len() % 2 != 0 || hex.len() < 10 {
+        return Err("record has an invalid length".to_string());
+    }
+    let mut bytes: Vec<u8> = hex.chunks(2).map(parse_hex_byte).collect::<Result<_, _>>()?;
+    if bytes[0] as usize + 5 != bytes.len() {
+        return Err(format!("byte count {} does not match the record", bytes[0]));
+    }
+    let (record, sum) = bytes.split_at(bytes.len() - 1);
+    if checksum(record) != sum[0] {
+        return Err(format!("checksum {:#04x} should be {:#04x}", sum[0], checksum(record)));
+    }
+    bytes.pop();
+    Ok(bytes)
+}
+
+pub fn parse(data: &[u8]) -> Result<MemoryImage, String> {
+    let text = std::str::from_utf8(data).map_err(|_| "Intel HEX file is not text".to_string())?;
+    let mut image = MemoryImage::new();
+    let mut base: u32 = 0;
+    let mut eof = false;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if eof {
+            return Err(format!("line {}: data after end of file record", n + 1));
+        }
+        let record = parse_record(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        let len = record[0] as usize;
+        let offset = ((record[1] as u32) << 8) | record[2] as u32;
+        let payload = &record[4..4+len];
+        let expect_len = |expected: usize| -> Result<(), String> {
+            match len == expected {
+                true => Ok(()),
+                false => Err(format!("line {}: record type {:#04x} needs {} data bytes", n + 1, record[3], expected)),
+            }
+        };
+
+        match record[3] {
+            DATA => image.insert(base.wrapping_add(offset), payload),
+            END_OF_FILE => eof = true,
+            EXTENDED_SEGMENT_ADDRESS => {
+                expect_len(2)?;
+                base = (((payload[0] as u32) << 8) | payload[1] as u32) << 4;
+            },
+            START_SEGMENT_ADDRESS => {
+                expect_len(4)?;
+                let cs = ((payload[0] as u32) << 8) | payload[1] as u32;
+                let ip = ((payload[2] as u32) << 8) | payload[3] as u32;
+                image.entry = Some((cs << 4) + ip);
+            },
+            EXTENDED_LINEAR_ADDRESS => {
+                expect_len(2)?;
+                base = (((payload[0] as u32) << 8) | payload[1] as u32) << 16;
+            },
+            START_LINEAR_ADDRESS => {
+                expect_len(4)?;
+                image.entry = Some(u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]));
+            },
+            other => return Err(format!("line {}: unknown record type {:#04x}", n + 1, other)),
+        }
+    }
+
+    if !eof {
+        return Err("Intel HEX file has no end of file record".to_string());
+    }
+    image.check_end()?;
+    Ok(image)
+}
+
+fn write_record(record_type: u8, offset: u16, data: &[u8], buf: &mut Vec<u8>) {
+    let mut record: Vec<u8> = vec![data.len() as u8];
+    record.extend_from_slice(&offset.to_be_bytes());
+    record.push(record_type);
+    record.extend_from_slice(data);
+    record.push(checksum(&record));
+
+    buf.push(b':');
+    for byte in record {
+        buf.extend_from_slice(format!("{:02X}", byte).as_bytes());
+    }
+    buf.push(b'\n');
+}
+
+pub fn write(image: &MemoryImage, buf: &mut Vec<u8>) {
+    let mut upper: Option<u16> = None;
+    for region in &image.regions {
+        let mut addr: u64 = region.addr as u64;
+        while addr < region.end() {
+            if upper != Some((addr >> 16) as u16) {
+                upper = Some((addr >> 16) as u16);
+                write_record(EXTENDED_LINEAR_ADDRESS, 0, &((addr >> 16) as u16).to_be_bytes(), buf);
+            }
+            /* Records never cross a 64 KiB boundary */
+            let end = u64::min(u64::min(addr + RECORD_LEN as u64, region.end()), (addr | 0xffff) + 1);
+            let i = (addr - region.addr as u64) as usize;
+            let j = (end - region.addr as u64) as usize;
+            write_record(DATA, addr as u16, &region.data[i..j], buf);
+            addr = end;
+        }
+    }
+    if let Some(entry) = image.entry {
+        write_record(START_LINEAR_ADDRESS, 0, &entry.to_be_bytes(), buf);
+    }
+    write_record(END_OF_FILE, 0, &[], buf);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ihex;
+    use crate::image::MemoryImage;
+
+    #[test]
+    fn test_ihex() {
+        let mut image = MemoryImage::new();
+        image.insert(0x1000fff8, &(0..20).collect::<Vec<u8>>());
+        image.entry = Some(0x10000119);
+
+        let mut buf = Vec::<u8>::new();
+        ihex::write(&image, &mut buf);
+        assert_eq!(String::from_utf8(buf.clone()).unwrap(), "\
+:020000041000EA
+:08FFF8000001020304050607E5
+:020000041001E9
+:0C00000008090A0B0C0D0E0F1011121352
+:0400000510000119CD
+:00000001FF
+");
+
+        let parsed = ihex::parse(&buf).unwrap();
+        assert_eq!(parsed.regions.len(), 1);
+        assert_eq!(parsed.regions[0].addr, 0x1000fff8);
+        assert_eq!(parsed.regions[0].data, image.regions[0].data);
+        assert_eq!(parsed.entry, Some(0x10000119));
+
+        assert!(ihex::parse(b":0400000510000119C4\n:00000001FF\n").is_err());
+        assert!(ihex::parse(b":0400000510000119CD\n").is_err());
+        assert!(ihex::parse(":0\u{e9}00000001FF0\n".as_bytes()).is_err());
+        assert!(ihex::parse(b":+0000001FF\n").is_err());
+        assert!(ihex::parse(b":02000004FFFFFC\n:02FFFF00AABB9B\n:00000001FF\n").is_err());
+        assert!(ihex::parse(b":02000004FFFFFC\n:02FFFE00AABB9C\n:00000001FF\n").is_ok());
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+
+use crate::elf::*;
+use crate::uf2::*;
+
+pub struct Region {
+    pub addr: u32,
+    pub data: Vec<u8>
+}
+
+impl Region {
+    pub fn end(&self) -> u64 {
+        self.addr as u64 + self.data.len() as u64
+    }
+}
+
+/* Sparse memory contents, kept as sorted, non-overlapping, non-adjacent regions */
+pub struct MemoryImage {
+    pub regions: Vec<Region>,
+    pub entry: Option<u32>
+}
+
+impl fmt::Display for MemoryImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for region in &self.regions {
+            writeln!(f, "{}", "-".repeat(70))?;
+            writeln!(f, "{0: <32} | {1: <35}", "Region",
+                     format!("{:#010x}..{:#010x} ({} bytes)", region.addr, region.end(), region.data.len()))?;
+        }
+        Ok(())
+    }
+}
+
+impl MemoryImage {
+    pub fn new() -> MemoryImage {
+        MemoryImage {
+            regions: Vec::new(),
+            entry: None
+        }
+    }
+
+    pub fn from_elf(elf: &Elf32, data: &[u8]) -> MemoryImage {
+        let mut image = MemoryImage::new();
+        for phdr in &elf.phdrs {
+            if phdr.p_type != 1 {
+                continue;
+            }
+
+            let file_size: usize = u32::min(phdr.p_memsz, phdr.p_filesz) as usize;
+            if file_size == 0 {
+                continue;
+            }
+
+            let offset: usize = phdr.p_offset as usize;
+            image.insert(phdr.p_paddr, &data[offset..offset+file_size]);
+        }
+        image.entry = Some(elf.ehdr.e_entry);
+        image
+    }
+
+    pub fn from_uf2(uf2: &Uf2) -> MemoryImage {
+        MemoryImage::from_blocks(uf2.blocks.iter())
+    }
+
+    pub fn from_uf2_family(uf2: &Uf2, family_id: u32) -> MemoryImage {
+        MemoryImage::from_blocks(uf2.blocks.iter()
+            .filter(|b| b.flags & HAS_FAMILY_ID != 0 && b.family_id == family_id))
+    }
+
+    fn from_blocks<'a>(blocks: impl Iterator<Item = &'a Uf2Block>) -> MemoryImage {
+        let mut image = MemoryImage::new();
+        for block in blocks {
+            if !block.is_valid() || block.flags & (FILE_CONTAINER | NOT_MAIN_FLASH) != 0 {
+                continue;
+            }
+            image.insert(block.target_addr, block.payload());
+        }
+        image
+    }
+
+    pub fn insert(&mut self, addr: u32, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let start: u64 = addr as u64;
+        let end: u64 = start + bytes.len() as u64;
+
+        /* Regions touching [start, end) get merged into one */
+        let first = self.regions.partition_point(|r| r.end() < start);
+        let last = self.regions.partition_point(|r| (r.addr as u64) <= end);
+
+        let merged_start: u64 = if first < last {
+            u64::min(start, self.regions[first].addr as u64)
+        } else {
+            start
+        };
+        let merged_end: u64 = if first < last {
+            u64::max(end, self.regions[last-1].end())
+        } else {
+            end
+        };
+
+        let mut data = vec![0u8; (merged_end - merged_start) as usize];
+        for region in &self.regions[first..last] {
+            let i = (region.addr as u64 - merged_start) as usize;
+            data[i..i+region.data.len()].copy_from_slice(&region.data);
+        }
+        let i = (start - merged_start) as usize;
+        data[i..i+bytes.len()].copy_from_slice(bytes);
+
+        let region = Region {
+            addr: merged_start as u32,
+            data
+        };
+        self.regions.splice(first..last, std::iter::once(region));
+    }
+
+    /* One region from the first to the last address, gaps set to `fill`, of at most `max_size` bytes */
+    pub fn fill_gaps(&mut self, fill: u8, max_size: u64) -> Result<(), String> {
+        let (start, end) = match (self.regions.first(), self.regions.last()) {
+            (Some(first), Some(last)) => (first.addr, last.end()),
+            _ => return Ok(()),
+        };
+        if end - start as u64 > max_size {
+            return Err(format!("Filling {:#x}..{:#x} would take {} bytes, more than the {} byte limit",
+                               start, end, end - start as u64, max_size));
+        }
+        let mut data = vec![fill; (end - start as u64) as usize];
+        for region in &self.regions {
+            let i = (region.addr - start) as usize;
+            data[i..i+region.data.len()].copy_from_slice(&region.data);
+        }
+        self.regions = vec![Region { addr: start, data }];
+        Ok(())
+    }
+
+    /* Extends the last region with `fill` up to a multiple of `align` */
+    pub fn pad(&mut self, align: u32, fill: u8) -> Result<(), String> {
+        if align == 0 {
+            return Err("Alignment must not be zero".to_string());
+        }
+        if let Some(last) = self.regions.last_mut() {
+            let end = last.end().next_multiple_of(align as u64);
+            if end > 1 << 32 {
+                return Err(format!("Padding {:#x} to {:#x} exceeds the 32-bit address space", last.end(), align));
+            }
+            last.data.resize((end - last.addr as u64) as usize, fill);
+        }
+        Ok(())
+    }
+
+    /* Extends the last region with `fill` until the image spans `size` bytes */
+    pub fn pad_to_size(&mut self, size: u32, fill: u8) -> Result<(), String> {
+        let start = match self.regions.first() {
+            Some(first) => first.addr as u64,
+            None => return Ok(()),
+        };
+        let last = self.regions.last_mut().unwrap();
+        if last.end() - start > size as u64 {
+            return Err(format!("Image {:#x}..{:#x} is larger than {} bytes", start, last.end(), size));
+        }
+        if start + size as u64 > 1 << 32 {
+            return Err(format!("{} bytes at {:#x} exceed the 32-bit address space", size, start));
+        }
+        last.data.resize((start + size as u64 - last.addr as u64) as usize, fill);
+        Ok(())
+    }
+
+    /* Copy with every address moved by `offset`, modulo 2^32 so large offsets move down */
+    pub fn offset(&self, offset: u32) -> Result<MemoryImage, String> {
+        let mut image = MemoryImage::new();
+        for region in &self.regions {
+            let addr = region.addr.wrapping_add(offset);
+            if addr as u64 + region.data.len() as u64 > 1 << 32 {
+                return Err(format!("Region {:#x}..{:#x} moved by {:#x} exceeds the 32-bit address space",
+                                   region.addr, region.end(), offset));
+            }
+            image.insert(addr, &region.data);
+        }
+        image.entry = self.entry.map(|e| e.wrapping_add(offset));
+        Ok(image)
+    }
+
+    /* Loaders call this, data past 2^32 would wrap around once addresses are u32 again */
+    pub fn check_end(&self) -> Result<(), String> {
+        match self.regions.last() {
+            Some(last) if last.end() > 1 << 32 => Err(format!("Region {:#x}..{:#x} exceeds the 32-bit address space",
+                                                               last.addr, last.end())),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn read(&self, addr: u32, len: usize) -> Option<&[u8]> {
+        let i = self.regions.partition_point(|r| r.end() <= addr as u64);
+        let region = self.regions.get(i)?;
+        if region.addr > addr {
+            return None;
+        }
+        let offset = (addr - region.addr) as usize;
+        region.data.get(offset..offset+len)
+    }
+
+    pub fn read_u32(&self, addr: u32) -> Option<u32> {
+        self.read(addr, 4).map(slice_to_u32)
+    }
+
+    pub fn read_u16(&self, addr: u32) -> Option<u16> {
+        self.read(addr, 2).map(slice_to_u16)
+    }
+
+    pub fn read_str(&self, addr: u32) -> Option<String> {
+        let i = self.regions.partition_point(|r| r.end() <= addr as u64);
+        let region = self.regions.get(i)?;
+        if region.addr > addr {
+            return None;
+        }
+        let bytes = &region.data[(addr - region.addr) as usize..];
+        let len = bytes.iter().position(|&b| b == 0)?;
+        Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::MemoryImage;
+
+    #[test]
+    fn test_insert_merges_regions() {
+        let mut image = MemoryImage::new();
+        image.insert(0x100, &[1, 2, 3, 4]);
+        image.insert(0x108, &[9, 9]);
+        assert_eq!(image.regions.len(), 2);
+
+        image.insert(0x104, &[5, 6, 7, 8]);
+        assert_eq!(image.regions.len(), 1);
+        assert_eq!(image.read(0x100, 10), Some(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 9][..]));
+
+        image.insert(0x102, &[0xaa]);
+        assert_eq!(image.read_u32(0x100), Some(0x04aa0201));
+        assert_eq!(image.read(0x108, 4), None);
+    }
+
+    #[test]
+    fn test_fill_and_pad() {
+        let mut image = MemoryImage::new();
+        image.insert(0x1000, &[1; 0x10]);
+        image.insert(0x1020, &[2; 0x10]);
+
+        let mut sparse = MemoryImage::new();
+        sparse.insert(0x10000000, &[1]);
+        sparse.insert(0x20000000, &[2]);
+        assert!(sparse.fill_gaps(0, 32 * 1024 * 1024).is_err());
+
+        image.fill_gaps(0xff, 0x1000).unwrap();
+        assert_eq!(image.regions.len(), 1);
+        assert_eq!(image.read(0x100f, 3), Some(&[1, 0xff, 0xff][..]));
+
+        image.pad(0x1000, 0xee).unwrap();
+        assert_eq!(image.regions[0].end(), 0x2000);
+        assert_eq!(image.read(0x1fff, 1), Some(&[0xee][..]));
+
+        image.pad_to_size(0x3000, 0).unwrap();
+        assert_eq!(image.regions[0].end(), 0x4000);
+        assert!(image.pad_to_size(0x1000, 0).is_err());
+        assert!(image.pad(0, 0).is_err());
+    }
+}
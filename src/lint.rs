@@ -0,0 +1,167 @@
+use std::fmt;
+
+use crate::uf2::*;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+pub struct Finding {
+    pub severity: Severity,
+    pub block: Option<usize>,
+    pub message: String
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        let location = match self.block {
+            Some(i) => format!("{} (block {})", severity, i),
+            None => severity.to_string(),
+        };
+        writeln!(f, "{0: <32} | {1: <35}", location, self.message)
+    }
+}
+
+/* RP2040 and RP2350 bootroms only write whole 256 byte flash pages */
+fn needs_page_alignment(family_id: u32) -> bool {
+    (0xe48bff56..=0xe48bff5b).contains(&family_id)
+}
+
+fn push(findings: &mut Vec<Finding>, severity: Severity, block: Option<usize>, message: String) {
+    findings.push(Finding { severity, block, message });
+}
+
+pub fn lint(uf2: &Uf2) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = Vec::new();
+    let num_blocks = uf2.blocks.len() as u32;
+    let mut block_nos: Vec<u32> = Vec::new();
+    let mut targets: Vec<(u32, u32, usize)> = Vec::new();
+    let mut families: Vec<u32> = Vec::new();
+
+    if uf2.blocks.is_empty() {
+        push(&mut findings, Severity::Error, None, "no blocks".to_string());
+    }
+
+    for (i, block) in uf2.blocks.iter().enumerate() {
+        for (name, found, expected) in [
+            ("magic_start0", block.magic_start0, MAGIC_START0),
+            ("magic_start1", block.magic_start1, MAGIC_START1),
+            ("magic_end", block.magic_end, MAGIC_END),
+        ] {
+            if found != expected {
+                push(&mut findings, Severity::Error, Some(i),
+                     format!("bad {} {:#010x}, expected {:#010x}", name, found, expected));
+            }
+        }
+
+        if block_nos.contains(&block.block_no) {
+            push(&mut findings, Severity::Error, Some(i), format!("duplicate block_no {}", block.block_no));
+        } else if block.block_no != i as u32 {
+            push(&mut findings, Severity::Error, Some(i), format!("block_no {}, expected {}", block.block_no, i));
+        }
+        block_nos.push(block.block_no);
+
+        if block.num_blocks != num_blocks {
+            push(&mut findings, Severity::Error, Some(i),
+                 format!("num_blocks {}, file has {}", block.num_blocks, num_blocks));
+        }
+
+        if block.payload_size > 476 {
+            push(&mut findings, Severity::Error, Some(i),
+                 format!("payload size {} is over 476", block.payload_size));
+        }
+
+        if block.flags & !KNOWN_FLAGS != 0 {
+            push(&mut findings, Severity::Warning, Some(i),
+                 format!("unknown flags {:#x}", block.flags & !KNOWN_FLAGS));
+        }
+
+        /* File containers reuse target_addr and family_id for offset and size */
+        if block.flags & FILE_CONTAINER != 0 {
+            continue;
+        }
+
+        let family_id: Option<u32> = match block.flags & HAS_FAMILY_ID {
+            0 => None,
+            _ => Some(block.family_id),
+        };
+        if let Some(id) = family_id.filter(|id| !families.contains(id)) {
+            if let Some(first) = families.first() {
+                push(&mut findings, Severity::Warning, Some(i),
+                     format!("family ID {:#x} differs from {:#x} of earlier blocks", id, first));
+            }
+            families.push(id);
+        }
+
+        if block.flags & NOT_MAIN_FLASH != 0 {
+            continue;
+        }
+
+        if family_id.is_some_and(needs_page_alignment) && !block.target_addr.is_multiple_of(256) {
+            push(&mut findings, Severity::Error, Some(i),
+                 format!("target address {:#010x} is not 256 byte aligned", block.target_addr));
+        }
+
+        let id = family_id.unwrap_or(0);
+        match targets.iter().find(|&&(addr, family, _)| addr == block.target_addr && family == id) {
+            Some(&(_, _, j)) => push(&mut findings, Severity::Warning, Some(i),
+                format!("target address {:#010x} already written by block {}", block.target_addr, j)),
+            None => targets.push((block.target_addr, id, i)),
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::*;
+
+    #[test]
+    fn test_lint() {
+        let mut uf2 = Uf2::new();
+        for i in 0..4 {
+            let mut block = Uf2Block::new();
+            block.magic_start0 = MAGIC_START0;
+            block.magic_start1 = MAGIC_START1;
+            block.magic_end = MAGIC_END;
+            block.flags = HAS_FAMILY_ID;
+            block.family_id = 0xe48bff59;
+            block.target_addr = 0x10000000 + i * 256;
+            block.payload_size = 256;
+            block.block_no = i;
+            block.num_blocks = 4;
+            uf2.blocks.push(block);
+        }
+        assert!(lint(&uf2).is_empty());
+
+        uf2.blocks[0].magic_end = 0;
+        uf2.blocks[1].target_addr = 0x10000080;
+        uf2.blocks[2].block_no = 1;
+        uf2.blocks[2].flags |= 0x10;
+        uf2.blocks[3].target_addr = 0x10000000;
+        uf2.blocks[3].num_blocks = 5;
+        uf2.blocks[3].payload_size = 480;
+        uf2.blocks[3].family_id = 0x68ed2b88;
+
+        let found: Vec<(Severity, Option<usize>)> = lint(&uf2).iter().map(|f| (f.severity, f.block)).collect();
+        assert_eq!(found, vec![
+            (Severity::Error, Some(0)),   /* magic_end */
+            (Severity::Error, Some(1)),   /* unaligned */
+            (Severity::Error, Some(2)),   /* duplicate block_no */
+            (Severity::Warning, Some(2)), /* unknown flags */
+            (Severity::Error, Some(3)),   /* num_blocks */
+            (Severity::Error, Some(3)),   /* payload size */
+            (Severity::Warning, Some(3)), /* mixed families */
+        ]);
+
+        uf2.blocks[3].family_id = 0xe48bff59;
+        uf2.blocks[3].payload_size = 256;
+        assert!(lint(&uf2).iter().any(|f| f.block == Some(3) && f.message.contains("already written by block 0")));
+    }
+}
@@ -1,19 +1,916 @@
 use std::fs;
 use std::env;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
+mod bin;
+mod carray;
 mod elf;
+mod ihex;
+mod md5;
+mod srec;
 mod uf2;
+mod image;
+mod binary_info;
 mod elf2uf2;
+mod uf22elf;
+mod board;
+mod deploy;
+mod target;
+mod diff;
+mod lint;
+mod verify;
+mod watch;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let infile_name: &str = &args[1];
-    let outfile_name: &str = &args[2];
+use crate::binary_info::BinaryInfo;
+use crate::elf::Elf32;
+use crate::image::MemoryImage;
+use crate::target::Target;
+use crate::uf2::{Uf2, Uf2Extension};
+
+fn parse_u32(s: &str) -> Result<u32, String> {
+    let result = match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.replace('_', "").parse::<u32>(),
+    };
+    result.map_err(|_| format!("Invalid number '{}'", s))
+}
+
+/* Options that take no value */
+const FLAGS: &[&str] = &["md5", "blocks", "fill-gaps", "dedup", "deploy"];
+
+/* Options that take a value, anything else is a typo rather than silently ignored */
+const VALUES: &[&str] = &[
+    "align", "base", "baseline", "description", "device-id", "drive", "family", "fill", "fw-version",
+    "interval", "machine", "max-size", "metadata", "module-name", "not-main-flash", "offset", "order",
+    "pad-to", "page-size", "payload-size", "protect", "range", "rebase", "search", "symbol", "target",
+    "target-file"
+];
+
+fn parse_range(s: &str) -> Result<(u32, u32), String> {
+    let (start, end) = s.split_once(':')
+        .ok_or(format!("Invalid range '{}', expected START:END", s))?;
+    Ok((parse_u32(start)?, parse_u32(end)?))
+}
+
+struct Options {
+    positional: Vec<String>,
+    values: Vec<(String, String)>,
+    flags: Vec<String>,
+    /* After `--`, or after the input in runner form */
+    rest: Vec<String>
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        Options::parse_args(args, false)
+    }
+
+    /* `elf2uf2 [OPTIONS] -d INPUT ARGS...` as run by cargo, ARGS are those of `cargo run --` */
+    fn parse_runner(args: &[String]) -> Result<Options, String> {
+        Options::parse_args(args, true)
+    }
+
+    fn parse_args(args: &[String], runner: bool) -> Result<Options, String> {
+        let mut options = Options {
+            positional: Vec::new(),
+            values: Vec::new(),
+            flags: Vec::new(),
+            rest: Vec::new()
+        };
+        let mut short_deploy = false;
+        let mut i = 0;
+        while i < args.len() {
+            if args[i] == "--" {
+                options.rest = args[i+1..].to_vec();
+                break;
+            }
+            if runner && short_deploy && !options.positional.is_empty() {
+                options.rest = args[i..].to_vec();
+                break;
+            }
+            if args[i] == "-d" {
+                options.flags.push("deploy".to_string());
+                short_deploy = true;
+                i += 1;
+            } else if let Some(name) = args[i].strip_prefix("--").filter(|n| FLAGS.contains(n)) {
+                options.flags.push(name.to_string());
+                i += 1;
+            } else if let Some(name) = args[i].strip_prefix("--") {
+                if !VALUES.contains(&name) {
+                    return Err(format!("Unknown option --{}", name));
+                }
+                let value = args.get(i+1)
+                    .ok_or(format!("Option --{} expects a value", name))?;
+                options.values.push((name.to_string(), value.clone()));
+                i += 2;
+            } else {
+                options.positional.push(args[i].clone());
+                i += 1;
+            }
+        }
+        Ok(options)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|n| n == name)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn values(&self, name: &str) -> Vec<&str> {
+        self.values.iter().filter(|(n, _)| n == name).map(|(_, v)| v.as_str()).collect()
+    }
+
+    fn u32_value(&self, name: &str) -> Result<Option<u32>, String> {
+        match self.value(name) {
+            Some(value) => parse_u32(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /* --fill, default 0 */
+    fn fill(&self) -> Result<u8, String> {
+        let fill = self.u32_value("fill")?.unwrap_or(0);
+        u8::try_from(fill).map_err(|_| format!("Invalid fill byte {:#x}", fill))
+    }
+
+    fn arg(&self, i: usize) -> Result<&str, String> {
+        self.positional.get(i).map(|s| s.as_str())
+            .ok_or("Missing argument, see README for usage".to_string())
+    }
+}
+
+fn uf2_info(uf2: &Uf2, options: &Options) -> Result<(), String> {
+    println!("{}", "=".repeat(70));
+    println!("{0: <32} | {1: <35}", "Blocks", uf2.blocks.len());
+    for family_id in uf2.family_ids() {
+        println!("{}", "-".repeat(70));
+        println!("{0: <32} | {1: <35}", "Family ID", format!("{:#x}", family_id));
+    }
+    let mut flag_counts: Vec<(u32, usize)> = Vec::new();
+    for block in &uf2.blocks {
+        match flag_counts.iter_mut().find(|(flags, _)| *flags == block.flags) {
+            Some((_, count)) => *count += 1,
+            None => flag_counts.push((block.flags, 1)),
+        }
+    }
+    for (flags, count) in flag_counts {
+        println!("{}", "-".repeat(70));
+        println!("{0: <32} | {1: <35}", format!("Flags ({} blocks)", count), uf2::flag_names(flags));
+    }
+    for extension in uf2.extensions() {
+        println!("{}", "-".repeat(70));
+        print!("{}", extension);
+    }
+    for file in uf2.files()? {
+        println!("{}", "-".repeat(70));
+        println!("{0: <32} | {1: <35}", "File", format!("{} ({} bytes)", file.name, file.data.len()));
+    }
+    let checksummed = uf2.blocks.iter().filter(|b| b.verify_md5().is_some()).count();
+    if checksummed > 0 {
+        let bad = uf2.verify_md5();
+        println!("{}", "-".repeat(70));
+        println!("{0: <32} | {1: <35}", "MD5 checksums",
+                 format!("{} ok, {} mismatching", checksummed - bad.len(), bad.len()));
+        for i in bad {
+            println!("{0: <32} | {1: <35}", "MD5 mismatch", format!("block {} at {:#x}", i, uf2.blocks[i].target_addr));
+        }
+    }
+    if options.flag("blocks") {
+        print!("{}", uf2);
+    }
+    Ok(())
+}
+
+fn info(options: &Options) -> Result<(), String> {
+    let infile_name = options.arg(0)?;
     let data: Vec<u8> = fs::read(infile_name)
-                        .expect("Should be a read file given as input");
-    let mut buf = Vec::<u8>::new();
+                        .map_err(|e| format!("{}: {}", infile_name, e))?;
+    let image = match input_format(infile_name, &data)? {
+        Format::Uf2 => {
+            let uf2 = Uf2::parse(&data)?;
+            uf2_info(&uf2, options)?;
+            MemoryImage::from_uf2(&uf2)
+        },
+        Format::Elf => {
+            let mut elf = Elf32::new();
+            elf.parse_elf(&data);
+            print!("{}", elf);
+            MemoryImage::from_elf(&elf, &data)
+        },
+        format => {
+            println!("{}", "=".repeat(70));
+            if format == Format::Srec {
+                let (_, header) = srec::parse(&data)?;
+                println!("{0: <32} | {1: <35}", "Module name", header);
+                println!("{}", "-".repeat(70));
+            }
+            let image = load_image(&format, &data, options)?;
+            let entry = image.entry.map(|e| format!("{:#x}", e)).unwrap_or("-".to_string());
+            println!("{0: <32} | {1: <35}", "Entry point", entry);
+            image
+        },
+    };
+    print!("{}", image);
+
+    println!("{}", "=".repeat(70));
+    match BinaryInfo::parse(&image) {
+        Some(binary_info) => {
+            println!("{0: <32} | {1: <35}", "Binary info", binary_info.program_name().unwrap_or("-"));
+            print!("{}", binary_info);
+        },
+        None => println!("{0: <32} | {1: <35}", "Binary info", "not found"),
+    }
+    Ok(())
+}
+
+#[derive(PartialEq)]
+enum Format {
+    Elf,
+    Uf2,
+    Bin,
+    Hex,
+    Srec,
+    C,
+    Rust
+}
+
+fn extension(file_name: &str) -> String {
+    Path::new(file_name).extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+fn input_format(file_name: &str, data: &[u8]) -> Result<Format, String> {
+    if data.starts_with(b"\x7fELF") {
+        return Ok(Format::Elf);
+    }
+    if Uf2::is_uf2(data) {
+        return Ok(Format::Uf2);
+    }
+    match extension(file_name).as_str() {
+        "bin"           => Ok(Format::Bin),
+        "hex" | "ihex"  => Ok(Format::Hex),
+        "srec" | "s19" | "s28" | "s37" | "mot" => Ok(Format::Srec),
+        _ if data.starts_with(b":") => Ok(Format::Hex),
+        _ if data.starts_with(b"S0") => Ok(Format::Srec),
+        _               => Err(format!("{}: unknown input format", file_name)),
+    }
+}
+
+fn output_format(file_name: &str) -> Result<Format, String> {
+    match extension(file_name).as_str() {
+        "uf2"           => Ok(Format::Uf2),
+        "bin"           => Ok(Format::Bin),
+        "hex" | "ihex"  => Ok(Format::Hex),
+        "srec" | "s19" | "s28" | "s37" | "mot" => Ok(Format::Srec),
+        "h" | "c"       => Ok(Format::C),
+        "rs"            => Ok(Format::Rust),
+        "elf"           => Err(format!("{}: ELF files are written by `elf2uf2 uf2elf`", file_name)),
+        _               => Err(format!("{}: unknown output format, expected .uf2, .bin, .hex, .srec, .h, .c or .rs", file_name)),
+    }
+}
+
+fn load_image(format: &Format, data: &[u8], options: &Options) -> Result<MemoryImage, String> {
+    match format {
+        Format::Elf => {
+            let mut elf = Elf32::new();
+            elf.parse_elf(data);
+            Ok(MemoryImage::from_elf(&elf, data))
+        },
+        Format::Uf2 => {
+            let uf2 = Uf2::parse(data)?;
+            match options.u32_value("family")? {
+                Some(family_id) => Ok(MemoryImage::from_uf2_family(&uf2, family_id)),
+                None => Ok(MemoryImage::from_uf2(&uf2)),
+            }
+        },
+        Format::Bin => {
+            let base_addr = options.u32_value("base")?
+                .ok_or("Raw binary input needs a --base address".to_string())?;
+            bin::parse(data, base_addr)
+        },
+        Format::Hex => ihex::parse(data),
+        Format::Srec => srec::parse(data).map(|(image, _)| image),
+        Format::C | Format::Rust => Err("C and Rust sources are output only".to_string()),
+    }
+}
+
+/* Profiles from --target-file, which may shadow built-in ones */
+fn user_targets(options: &Options) -> Result<Vec<Target>, String> {
+    match options.value("target-file") {
+        Some(file_name) => {
+            let text = fs::read_to_string(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+            Target::parse_toml(&text).map_err(|e| format!("{}: {}", file_name, e))
+        },
+        None => Ok(Vec::new()),
+    }
+}
+
+fn target_option(options: &Options) -> Result<Option<Target>, String> {
+    match options.value("target") {
+        Some(name) => Target::find(name, &user_targets(options)?)
+            .map(Some).ok_or(format!("Unknown target '{}'", name)),
+        None => Ok(None),
+    }
+}
+
+fn elf2uf2_options(options: &Options) -> Result<elf2uf2::Elf2Uf2, String> {
     let mut elf2uf2 = elf2uf2::Elf2Uf2::new();
-    elf2uf2.convert(&data, &mut buf);
+    let target = target_option(options)?;
+    let erase_size = target.as_ref().map(|t| t.erase_size);
+    if let Some(target) = target {
+        elf2uf2.set_target(target);
+    }
+    if let Some(version) = options.value("fw-version") {
+        elf2uf2.extensions.push(Uf2Extension::version(version));
+    }
+    if let Some(description) = options.value("description") {
+        elf2uf2.extensions.push(Uf2Extension::description(description));
+    }
+    if let Some(page_size) = options.u32_value("page-size")? {
+        elf2uf2.extensions.push(Uf2Extension::page_size(page_size));
+    }
+    if let Some(device_id) = options.u32_value("device-id")? {
+        elf2uf2.extensions.push(Uf2Extension::device_id(device_id));
+    }
+    elf2uf2.md5 = options.flag("md5");
+    elf2uf2.family_id = options.u32_value("family")?;
+    if let Some(payload_size) = options.u32_value("payload-size")? {
+        elf2uf2.payload_size = payload_size;
+    }
+    for range in options.values("not-main-flash") {
+        elf2uf2.not_main_flash.push(parse_range(range)?);
+    }
+    for metadata in options.values("metadata") {
+        let (addr, file_name) = metadata.split_once(':')
+            .ok_or(format!("Invalid metadata '{}', expected ADDR:FILE", metadata))?;
+        let data: Vec<u8> = fs::read(file_name)
+                            .map_err(|e| format!("{}: {}", file_name, e))?;
+        elf2uf2.metadata.push((parse_u32(addr)?, data));
+    }
+    elf2uf2.order = match options.value("order") {
+        None | Some("elf")  => elf2uf2::BlockOrder::Elf,
+        Some("address")     => elf2uf2::BlockOrder::Address,
+        Some("boot")        => elf2uf2::BlockOrder::BootFirst,
+        Some(order)         => return Err(format!("Invalid block order '{}', expected elf, address or boot", order)),
+    };
+    elf2uf2.dedup = options.flag("dedup");
+    for range in options.values("protect") {
+        elf2uf2.protected.push(parse_range(range)?);
+    }
+    elf2uf2.fill_gaps = options.flag("fill-gaps");
+    elf2uf2.max_size = max_size(options)?;
+    elf2uf2.align = match options.value("align") {
+        Some("erase") => Some(erase_size.ok_or("--align erase needs a --target".to_string())?),
+        _ => options.u32_value("align")?,
+    };
+    elf2uf2.total_size = options.u32_value("pad-to")?;
+    elf2uf2.fill = options.fill()?;
+    if let Some(baseline_name) = options.value("baseline") {
+        let data: Vec<u8> = fs::read(baseline_name)
+                            .map_err(|e| format!("{}: {}", baseline_name, e))?;
+        let format = input_format(baseline_name, &data)?;
+        elf2uf2.baseline = Some(load_image(&format, &data, options)?);
+    }
+    Ok(elf2uf2)
+}
+
+/* Limit for flat images and filled gaps */
+fn max_size(options: &Options) -> Result<u64, String> {
+    Ok(options.u32_value("max-size")?.map(u64::from).unwrap_or(bin::MAX_BIN_SIZE))
+}
+
+/* --offset or --rebase, which moves the lowest address to the given one */
+fn image_offset(image: &MemoryImage, options: &Options) -> Result<u32, String> {
+    match (options.u32_value("offset")?, options.u32_value("rebase")?) {
+        (Some(_), Some(_)) => Err("Use either --offset or --rebase".to_string()),
+        (Some(offset), None) => Ok(offset),
+        (None, Some(base)) => Ok(image.regions.first().map(|r| base.wrapping_sub(r.addr)).unwrap_or(0)),
+        (None, None) => Ok(0),
+    }
+}
+
+/* --offset, --rebase, --fill-gaps, --align and --pad-to for outputs other than UF2 */
+fn padded_image(format: &Format, data: &[u8], options: &Options) -> Result<MemoryImage, String> {
+    let image = load_image(format, data, options)?;
+    let mut image = image.offset(image_offset(&image, options)?)?;
+    let fill = options.fill()?;
+    if options.flag("fill-gaps") {
+        image.fill_gaps(fill, max_size(options)?)?;
+    }
+    if let Some(align) = options.u32_value("align")? {
+        image.pad(align, fill)?;
+    }
+    if let Some(size) = options.u32_value("pad-to")? {
+        image.pad_to_size(size, fill)?;
+    }
+    Ok(image)
+}
+
+fn to_uf2(format: &Format, data: &[u8], options: &Options, buf: &mut Vec<u8>) -> Result<(), String> {
+    let mut elf2uf2 = elf2uf2_options(options)?;
+    elf2uf2.offset = image_offset(&load_image(format, data, options)?, options)?;
+    match format {
+        Format::Elf => elf2uf2.convert(data, buf),
+        _ => elf2uf2.convert_image(&load_image(format, data, options)?, buf),
+    }
+}
+
+/* Without an output the input's extension is replaced, which is how cargo runs `elf2uf2 -d` */
+fn outfile_name(options: &Options) -> Result<String, String> {
+    let infile_name = options.arg(0)?;
+    match options.positional.get(1) {
+        Some(name) => Ok(name.clone()),
+        None => Path::new(infile_name).with_extension("uf2").to_str()
+                    .map(|name| name.to_string())
+                    .ok_or(format!("{}: not a file name", infile_name)),
+    }
+}
+
+fn convert(options: &Options) -> Result<(), String> {
+    let infile_name = options.arg(0)?;
+    let outfile_name = &outfile_name(options)?;
+    if let Some(arg) = options.positional.get(2) {
+        return Err(format!("Unexpected argument '{}'", arg));
+    }
+    if outfile_name == infile_name {
+        return match options.positional.get(1) {
+            Some(_) => Err(format!("Output {} would overwrite the input", outfile_name)),
+            None => Err(format!("{} is already a UF2 file", infile_name)),
+        };
+    }
+    let output = output_format(outfile_name)?;
+    if options.flag("deploy") && output != Format::Uf2 {
+        return Err(format!("Only UF2 files can be deployed, not {}", outfile_name));
+    }
+    if !options.rest.is_empty() {
+        eprintln!("note: ignoring arguments {}", options.rest.join(" "));
+    }
+    let data: Vec<u8> = fs::read(infile_name)
+                        .map_err(|e| format!("{}: {}", infile_name, e))?;
+    let format = input_format(infile_name, &data)?;
+    let mut buf = Vec::<u8>::new();
+    match output {
+        Format::Uf2 => {
+            if format == Format::Uf2 {
+                return Err(format!("{} is already a UF2 file", infile_name));
+            }
+            to_uf2(&format, &data, options, &mut buf)?;
+        },
+        Format::Bin => {
+            bin::write(&padded_image(&format, &data, options)?, options.fill()?, max_size(options)?, &mut buf)?;
+        },
+        Format::Hex => ihex::write(&padded_image(&format, &data, options)?, &mut buf),
+        Format::Srec => {
+            let stem = Path::new(outfile_name).file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            let header = options.value("module-name").unwrap_or(stem);
+            srec::write(&padded_image(&format, &data, options)?, header, &mut buf)?;
+        },
+        Format::C | Format::Rust => {
+            let stem = Path::new(outfile_name).file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            let name = options.value("symbol").unwrap_or(stem);
+            let image = padded_image(&format, &data, options)?;
+            match output {
+                Format::C => carray::write_c(&image, name, &mut buf),
+                _ => carray::write_rust(&image, name, &mut buf),
+            }
+        },
+        Format::Elf => unreachable!(),
+    }
+
+    fs::write(outfile_name, &buf).map_err(|e| format!("{}: {}", outfile_name, e))?;
+    if options.flag("deploy") {
+        deploy_uf2(infile_name, &buf, options)?;
+    }
+    Ok(())
+}
+
+/* FILE[,offset=N][,family=N], raw binaries are placed at their offset */
+fn merge_input(elf2uf2: &mut elf2uf2::Elf2Uf2, spec: &str, options: &Options) -> Result<(), String> {
+    let mut parts = spec.split(',');
+    let infile_name = parts.next().unwrap_or("");
+    let mut offset: u32 = 0;
+    let mut family_id: Option<u32> = None;
+    for part in parts {
+        match part.split_once('=') {
+            Some(("offset", value)) => offset = parse_u32(value)?,
+            Some(("family", value)) => family_id = Some(parse_u32(value)?),
+            _ => return Err(format!("Invalid input option '{}' in '{}'", part, spec)),
+        }
+    }
+
+    let data: Vec<u8> = fs::read(infile_name)
+                        .map_err(|e| format!("{}: {}", infile_name, e))?;
+    match input_format(infile_name, &data)? {
+        Format::Elf => elf2uf2.add_elf(&data, offset, family_id),
+        Format::Uf2 => {
+            /* Each family of the UF2 is kept, or written as `family` when one is given */
+            let uf2 = Uf2::parse(&data)?;
+            let images: Vec<(MemoryImage, Option<u32>)> = match uf2.family_ids().as_slice() {
+                [] => vec![(MemoryImage::from_uf2(&uf2), family_id)],
+                ids => ids.iter().map(|id| (MemoryImage::from_uf2_family(&uf2, *id), family_id.or(Some(*id)))).collect(),
+            };
+            if images.iter().all(|(image, _)| image.regions.is_empty()) {
+                return Err(format!("{}: UF2 contains no data", infile_name));
+            }
+            for (image, id) in images {
+                elf2uf2.add_image(&image, offset, id)?;
+            }
+            Ok(())
+        },
+        Format::Bin => elf2uf2.add_image(&bin::parse(&data, 0)?, offset, family_id),
+        format => elf2uf2.add_image(&load_image(&format, &data, options)?, offset, family_id),
+    }.map_err(|e| format!("{}: {}", infile_name, e))
+}
+
+fn merge(options: &Options) -> Result<(), String> {
+    let outfile_name = options.arg(0)?;
+    options.arg(1)?;
+    let mut elf2uf2 = elf2uf2_options(options)?;
+    for spec in &options.positional[1..] {
+        merge_input(&mut elf2uf2, spec, options)?;
+    }
+    let mut buf = Vec::<u8>::new();
+    elf2uf2.write(&mut buf)?;
+    fs::write(outfile_name, buf).map_err(|e| format!("{}: {}", outfile_name, e))
+}
+
+fn uf2elf(options: &Options) -> Result<(), String> {
+    let infile_name = options.arg(0)?;
+    let outfile_name = options.arg(1)?;
+    let data: Vec<u8> = fs::read(infile_name)
+                        .map_err(|e| format!("{}: {}", infile_name, e))?;
+    let mut buf = Vec::<u8>::new();
+    let mut uf22elf = uf22elf::Uf22Elf::new();
+    uf22elf.family_id = options.u32_value("family")?;
+    if let Some(e_machine) = options.u32_value("machine")? {
+        uf22elf.e_machine = Some(u16::try_from(e_machine)
+            .map_err(|_| format!("Invalid ELF machine {}", e_machine))?);
+    }
+    uf22elf.convert(&data, &mut buf)?;
+    fs::write(outfile_name, buf).map_err(|e| format!("{}: {}", outfile_name, e))
+}
+
+fn verify(options: &Options) -> Result<(), String> {
+    let elffile_name = options.arg(0)?;
+    let uf2file_name = options.arg(1)?;
+    let elf_data: Vec<u8> = fs::read(elffile_name)
+                        .map_err(|e| format!("{}: {}", elffile_name, e))?;
+    let uf2_data: Vec<u8> = fs::read(uf2file_name)
+                        .map_err(|e| format!("{}: {}", uf2file_name, e))?;
+    if input_format(elffile_name, &elf_data)? != Format::Elf {
+        return Err(format!("{}: not an ELF file", elffile_name));
+    }
+    let report = verify::verify(&elf_data, &uf2_data, options.u32_value("family")?, target_option(options)?.as_ref())?;
+    print!("{}", report);
+    match report.issues.len() {
+        0 => Ok(()),
+        n => Err(format!("{} does not match {} ({} issues)", uf2file_name, elffile_name, n)),
+    }
+}
+
+fn lint(options: &Options) -> Result<(), String> {
+    let infile_name = options.arg(0)?;
+    let data: Vec<u8> = fs::read(infile_name)
+                        .map_err(|e| format!("{}: {}", infile_name, e))?;
+    let findings = lint::lint(&Uf2::parse(&data)?);
+    for finding in &findings {
+        print!("{}", finding);
+    }
+    let errors = findings.iter().filter(|f| f.severity == lint::Severity::Error).count();
+    println!("{}", "=".repeat(70));
+    println!("{0: <32} | {1: <35}", "Result",
+             format!("{} errors, {} warnings", errors, findings.len() - errors));
+    match errors {
+        0 => Ok(()),
+        n => Err(format!("{}: {} errors", infile_name, n)),
+    }
+}
+
+fn split(options: &Options) -> Result<(), String> {
+    let infile_name = options.arg(0)?;
+    let outdir = Path::new(options.arg(1)?);
+    let data: Vec<u8> = fs::read(infile_name)
+                        .map_err(|e| format!("{}: {}", infile_name, e))?;
+    let uf2 = Uf2::parse(&data)?;
+    let stem = Path::new(infile_name).file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("split");
+
+    let ranges = options.values("range").iter()
+        .map(|r| parse_range(r))
+        .collect::<Result<Vec<(u32, u32)>, String>>()?;
+    let parts: Vec<(String, Uf2)> = match ranges.is_empty() {
+        true => uf2.split_by_family().into_iter().map(|(id, part)| match id {
+            Some(id) => (format!("{}-{:08x}.uf2", stem, id), part),
+            None => (format!("{}-nofamily.uf2", stem), part),
+        }).collect(),
+        false => ranges.iter().zip(uf2.split_by_range(&ranges))
+            .map(|((start, end), part)| (format!("{}-{:08x}-{:08x}.uf2", stem, start, end), part))
+            .collect(),
+    };
+
+    for (name, part) in parts {
+        let path = outdir.join(name);
+        let mut buf = Vec::<u8>::new();
+        part.write(&mut buf);
+        fs::write(&path, buf).map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("{} ({} blocks)", path.display(), part.blocks.len());
+    }
+    Ok(())
+}
+
+fn diff(options: &Options) -> Result<(), String> {
+    let mut images: Vec<MemoryImage> = Vec::new();
+    let mut elf_names = None;
+    let mut uf2_pages: Vec<Option<u32>> = Vec::new();
+    for i in 0..2 {
+        let infile_name = options.arg(i)?;
+        let data: Vec<u8> = fs::read(infile_name)
+                            .map_err(|e| format!("{}: {}", infile_name, e))?;
+        let format = input_format(infile_name, &data)?;
+        /* Names come from the newer ELF if both are ELF files */
+        if format == Format::Elf {
+            let mut elf = Elf32::new();
+            elf.parse_elf(&data);
+            elf_names = Some((elf.sections(), elf.symbols(&data)));
+        }
+        uf2_pages.push(match format {
+            Format::Uf2 => Uf2::parse(&data)?.blocks.first().map(|b| b.payload_size).filter(|&s| s > 0),
+            _ => None,
+        });
+        images.push(load_image(&format, &data, options)?);
+    }
+
+    /* Pages of a UF2 compared to another format are padded with zeros, which is no change */
+    let page_size = match (uf2_pages[0], uf2_pages[1]) {
+        (Some(size), None) | (None, Some(size)) => Some(size),
+        _ => None,
+    };
+    let mut diff = diff::Diff::new(&images[0], &images[1], page_size);
+    if let Some((sections, symbols)) = elf_names {
+        diff.sections = sections;
+        diff.symbols = symbols;
+    }
+    print!("{}", diff);
+    Ok(())
+}
+
+fn targets(options: &Options) -> Result<(), String> {
+    let user = user_targets(options)?;
+    for target in user.iter().chain(Target::builtin().iter()
+            .filter(|t| !user.iter().any(|u| u.name.to_lowercase() == t.name))) {
+        print!("{}", target);
+    }
+    Ok(())
+}
+
+/* Drives among the FAT mount points and automount directories, or below the --search directories */
+fn find_drives(options: &Options) -> Vec<deploy::Drive> {
+    match options.values("search") {
+        search if search.is_empty() => deploy::find_drives(&deploy::default_mounts(), &deploy::default_roots()),
+        search => deploy::find_drives(&[], &search.iter().map(|s| Path::new(s).to_path_buf()).collect::<Vec<_>>()),
+    }
+}
+
+/* Bootloader drives currently mounted and the target each one was identified as */
+fn boards(options: &Options) -> Result<(), String> {
+    let user = user_targets(options)?;
+    let drives = find_drives(options);
+    for drive in &drives {
+        println!("{}", "=".repeat(70));
+        println!("{0: <32} | {1: <35}", "Drive", drive.path.display());
+        print!("{}", drive.info);
+        println!("{}", "-".repeat(70));
+        println!("{0: <32} | {1: <35}", "Target", match drive.info.target(&user) {
+            Some(target) => target.name,
+            None => "unknown".to_string(),
+        });
+        println!("{}", "-".repeat(70));
+        println!("{0: <32} | {1: <35}", "Family IDs", drive.info.family_ids(&user).iter()
+                 .map(|id| format!("{:#x}", id)).collect::<Vec<_>>().join(", "));
+    }
+    if drives.is_empty() {
+        return Err(format!("No mounted drive with {} found", deploy::INFO_FILE));
+    }
+    Ok(())
+}
+
+/* Converts unless the input already is a UF2, then copies it to the matching bootloader drive */
+fn deploy(options: &Options) -> Result<(), String> {
+    let infile_name = options.arg(0)?;
+    let data: Vec<u8> = fs::read(infile_name)
+                        .map_err(|e| format!("{}: {}", infile_name, e))?;
+    let format = input_format(infile_name, &data)?;
+    let mut buf = Vec::<u8>::new();
+    match format {
+        Format::Uf2 => buf = data,
+        _ => to_uf2(&format, &data, options, &mut buf)?,
+    }
+
+    deploy_uf2(infile_name, &buf, options)
+}
+
+/* Writes `buf` to --drive, or the one matching drive found, named after the input */
+fn deploy_uf2(infile_name: &str, buf: &[u8], options: &Options) -> Result<(), String> {
+    let user = user_targets(options)?;
+    let family_ids = Uf2::parse(buf)?.family_ids();
+    let drive = match options.value("drive") {
+        Some(drive) => {
+            let drive = Path::new(drive).to_path_buf();
+            /* Explicitly chosen drives are used anyway, but a wrong board is worth a warning */
+            if let Ok(text) = fs::read_to_string(drive.join(deploy::INFO_FILE)) {
+                let info = board::BoardInfo::parse(&text);
+                if info.accepts(&family_ids, &user) == Some(false) {
+                    eprintln!("warning: {} ({}) does not take family {}", drive.display(),
+                              info.board_id.as_deref().unwrap_or(""),
+                              family_ids.iter().map(|id| format!("{:#x}", id)).collect::<Vec<_>>().join(", "));
+                }
+            }
+            drive
+        },
+        None => deploy::select(&find_drives(options), &family_ids, &user)?.path.clone(),
+    };
+    let name = Path::new(infile_name).with_extension("uf2");
+    let name = name.file_name().and_then(|n| n.to_str()).unwrap_or("firmware.uf2");
+    let path = deploy::write(&drive, name, buf)?;
+    println!("{} ({} bytes)", path.display(), buf.len());
+    Ok(())
+}
+
+/* Converts again, and deploys with -d, whenever the input has changed and is completely written */
+fn watch(options: &Options) -> Result<(), String> {
+    let infile_name = options.arg(0)?;
+    let outfile_name = outfile_name(options)?;
+    let interval = match options.u32_value("interval")?.unwrap_or(500) {
+        0 => return Err("Interval must be at least 1 ms".to_string()),
+        ms => Duration::from_millis(ms as u64),
+    };
+    let mut watcher = watch::Watcher::new(Path::new(infile_name));
+    let mut rebuilds = 0;
+    println!("Watching {}, press Ctrl-C to stop", infile_name);
+    loop {
+        if watcher.poll() {
+            rebuilds += 1;
+            let start = Instant::now();
+            match convert(options) {
+                Ok(()) => println!("[{}] {} -> {} ({} bytes in {} ms)", rebuilds, infile_name, outfile_name,
+                                   fs::metadata(&outfile_name).map(|m| m.len()).unwrap_or(0),
+                                   start.elapsed().as_millis()),
+                Err(e) => eprintln!("[{}] error: {}", rebuilds, e),
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+fn pack(options: &Options) -> Result<(), String> {
+    let outfile_name = options.arg(0)?;
+    options.arg(1)?;
+    let mut uf2 = Uf2::new();
+    for infile_name in &options.positional[1..] {
+        let data: Vec<u8> = fs::read(infile_name)
+                            .map_err(|e| format!("{}: {}", infile_name, e))?;
+        let name = Path::new(infile_name).file_name()
+                    .and_then(|n| n.to_str())
+                    .ok_or(format!("{}: not a file name", infile_name))?;
+        uf2.add_file(name, &data)?;
+    }
+    let mut buf = Vec::<u8>::new();
+    uf2.write(&mut buf);
+    fs::write(outfile_name, buf).map_err(|e| format!("{}: {}", outfile_name, e))
+}
+
+fn unpack(options: &Options) -> Result<(), String> {
+    let infile_name = options.arg(0)?;
+    let outdir = Path::new(options.arg(1)?);
+    let data: Vec<u8> = fs::read(infile_name)
+                        .map_err(|e| format!("{}: {}", infile_name, e))?;
+    for file in Uf2::parse(&data)?.files()? {
+        /* Only the final component, so names cannot escape the output directory */
+        let name = Path::new(&file.name).file_name()
+                    .ok_or(format!("Invalid file name '{}'", file.name))?;
+        let path = outdir.join(name);
+        fs::write(&path, &file.data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("{} ({} bytes)", path.display(), file.data.len());
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let result = match args.get(1).map(|s| s.as_str()) {
+        Some("info")    => Options::parse(&args[2..]).and_then(|o| info(&o)),
+        Some("uf2elf")  => Options::parse(&args[2..]).and_then(|o| uf2elf(&o)),
+        Some("verify")  => Options::parse(&args[2..]).and_then(|o| verify(&o)),
+        Some("lint") | Some("check") => Options::parse(&args[2..]).and_then(|o| lint(&o)),
+        Some("merge")   => Options::parse(&args[2..]).and_then(|o| merge(&o)),
+        Some("split")   => Options::parse(&args[2..]).and_then(|o| split(&o)),
+        Some("diff")    => Options::parse(&args[2..]).and_then(|o| diff(&o)),
+        Some("targets") => Options::parse(&args[2..]).and_then(|o| targets(&o)),
+        Some("deploy")  => Options::parse(&args[2..]).and_then(|o| deploy(&o)),
+        Some("boards")  => Options::parse(&args[2..]).and_then(|o| boards(&o)),
+        Some("watch")   => Options::parse(&args[2..]).and_then(|o| watch(&o)),
+        Some("pack")    => Options::parse(&args[2..]).and_then(|o| pack(&o)),
+        Some("unpack")  => Options::parse(&args[2..]).and_then(|o| unpack(&o)),
+        _               => Options::parse_runner(&args[1..]).and_then(|o| convert(&o)),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options::parse_runner(&args("--target rp2040 -d target/app --verbose 1")).unwrap();
+        assert!(options.flag("deploy"));
+        assert_eq!(options.value("target"), Some("rp2040"));
+        assert_eq!(options.positional, vec!["target/app"]);
+        assert_eq!(options.rest, args("--verbose 1"));
+        assert_eq!(outfile_name(&options).unwrap(), "target/app.uf2");
+
+        /* Only the short form before the input makes the rest pass through */
+        let options = Options::parse_runner(&args("--deploy app.elf out/app.uf2")).unwrap();
+        assert_eq!(options.positional, args("app.elf out/app.uf2"));
+        assert!(options.rest.is_empty());
+        assert_eq!(outfile_name(&options).unwrap(), "out/app.uf2");
+        assert_eq!(watch(&Options::parse(&args("app.elf --interval 0")).unwrap()),
+                   Err("Interval must be at least 1 ms".to_string()));
+        let options = Options::parse(&args("-d app.elf out/app.uf2")).unwrap();
+        assert_eq!(options.positional, args("app.elf out/app.uf2"));
+        assert_eq!(convert(&Options::parse_runner(&args("a.elf b.uf2 c.uf2")).unwrap()),
+                   Err("Unexpected argument 'c.uf2'".to_string()));
+        assert_eq!(convert(&Options::parse(&args("a.bin a.bin")).unwrap()),
+                   Err("Output a.bin would overwrite the input".to_string()));
+        assert_eq!(convert(&Options::parse(&args("a.uf2")).unwrap()),
+                   Err("a.uf2 is already a UF2 file".to_string()));
+        assert!(convert(&Options::parse(&args("a.uf2 a.elf")).unwrap()).unwrap_err().contains("uf2elf"));
+        assert!(convert(&Options::parse(&args("a.uf2 a.out")).unwrap()).unwrap_err().contains("unknown output format"));
+
+        let options = Options::parse(&args("--md5 app.elf -- --family 1")).unwrap();
+        assert!(options.flag("md5"));
+        assert_eq!(options.value("family"), None);
+        assert_eq!(options.rest, args("--family 1"));
+        assert_eq!(outfile_name(&options).unwrap(), "app.uf2");
+        assert!(Options::parse(&args("app.elf --family")).is_err());
+        assert_eq!(Options::parse(&args("app.elf --fill 0xff")).unwrap().fill(), Ok(0xff));
+        assert_eq!(Options::parse(&args("app.elf --fill 0x100")).unwrap().fill(), Err("Invalid fill byte 0x100".to_string()));
+        assert_eq!(Options::parse(&args("app.elf --protct 0x10000000")).err(), Some("Unknown option --protct".to_string()));
+        assert_eq!(Options::parse_runner(&args("--md6 -d app.elf")).err(), Some("Unknown option --md6".to_string()));
+    }
+
+    #[test]
+    fn test_merge_input() {
+        let path = std::env::temp_dir().join(format!("elf2uf2-merge-{}.uf2", std::process::id()));
+        let name = path.to_str().unwrap();
+        let options = Options::parse(&[]).unwrap();
+        let merged = |spec: &str| -> Result<Uf2, String> {
+            let mut elf2uf2 = elf2uf2::Elf2Uf2::new();
+            merge_input(&mut elf2uf2, spec, &options)?;
+            let mut buf = Vec::<u8>::new();
+            elf2uf2.write(&mut buf)?;
+            Uf2::parse(&buf)
+        };
+
+        let mut image = MemoryImage::new();
+        image.insert(0x10000000, &[1; 0x100]);
+        let mut elf2uf2 = elf2uf2::Elf2Uf2::new();
+        elf2uf2.add_image(&image, 0, Some(0xe48bff56)).unwrap();
+        let mut buf = Vec::<u8>::new();
+        elf2uf2.write(&mut buf).unwrap();
+        fs::write(&path, &buf).unwrap();
+        assert_eq!(merged(name).unwrap().family_ids(), vec![0xe48bff56]);
+        assert_eq!(merged(&format!("{},family=0xe48bff59", name)).unwrap().family_ids(), vec![0xe48bff59]);
+
+        /* Without family IDs, the blocks take the given family */
+        let mut uf2 = Uf2::parse(&buf).unwrap();
+        uf2.blocks[0].flags &= !uf2::HAS_FAMILY_ID;
+        buf.clear();
+        uf2.write(&mut buf);
+        fs::write(&path, &buf).unwrap();
+        assert_eq!(merged(&format!("{},family=0x58", name)).unwrap().family_ids(), vec![0x58]);
 
-    fs::write(outfile_name, buf).unwrap();
+        uf2.blocks[0].flags |= uf2::NOT_MAIN_FLASH;
+        buf.clear();
+        uf2.write(&mut buf);
+        fs::write(&path, &buf).unwrap();
+        assert!(merged(name).err().unwrap().ends_with("UF2 contains no data"));
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,166 @@
+use crate::ihex::parse_hex_byte;
+use crate::image::MemoryImage;
+
+const RECORD_LEN: usize = 16;
+
+fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/* Address width in bytes for the data and termination record types */
+fn address_len(record_type: u8) -> Option<usize> {
+    match record_type {
+        b'0' | b'1' | b'5' | b'9'   => Some(2),
+        b'2' | b'6' | b'8'          => Some(3),
+        b'3' | b'7'                 => Some(4),
+        _                           => None,
+    }
+}
+
+fn parse_record(line: &str) -> Result<(u8, u32, Vec<u8>), String> {
+    let bytes = line.as_bytes();
+    if bytes.len() < 4 || bytes[0] != b'S' || !bytes.len().is_multiple_of(2) {
+        return Err("record does not start with 'S' or has an invalid length".to_string());
+    }
+    let record_type = bytes[1];
+    let addr_len = address_len(record_type)
+        .ok_or(format!("unknown record type S{}", record_type as char))?;
+
+    let record: Vec<u8> = bytes[2..].chunks(2).map(parse_hex_byte).collect::<Result<_, _>>()?;
+    if record[0] as usize + 1 != record.len() || record.len() < addr_len + 2 {
+        return Err(format!("byte count {} does not match the record", record[0]));
+    }
+    let (body, sum) = record.split_at(record.len() - 1);
+    if checksum(body) != sum[0] {
+        return Err(format!("checksum {:#04x} should be {:#04x}", sum[0], checksum(body)));
+    }
+
+    let addr = body[1..1+addr_len].iter().fold(0u32, |addr, b| (addr << 8) | *b as u32);
+    Ok((record_type, addr, body[1+addr_len..].to_vec()))
+}
+
+/* Image and the module name from the S0 header */
+pub fn parse(data: &[u8]) -> Result<(MemoryImage, String), String> {
+    let text = std::str::from_utf8(data).map_err(|_| "S-record file is not text".to_string())?;
+    let mut image = MemoryImage::new();
+    let mut header = String::new();
+    let mut count: u32 = 0;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (record_type, addr, payload) = parse_record(line)
+            .map_err(|e| format!("line {}: {}", n + 1, e))?;
+        match record_type {
+            b'0' => header = String::from_utf8_lossy(&payload).trim_end_matches('\0').to_string(),
+            b'1' | b'2' | b'3' => {
+                image.insert(addr, &payload);
+                count += 1;
+            },
+            b'5' | b'6' => {
+                if addr != count {
+                    return Err(format!("line {}: record count {} does not match {} data records", n + 1, addr, count));
+                }
+            },
+            _ => image.entry = Some(addr),
+        }
+    }
+    image.check_end()?;
+    Ok((image, header))
+}
+
+fn write_record(record_type: u8, addr: u32, data: &[u8], buf: &mut Vec<u8>) {
+    let addr_len = address_len(record_type).unwrap();
+    let mut record: Vec<u8> = vec![(addr_len + data.len() + 1) as u8];
+    record.extend_from_slice(&addr.to_be_bytes()[4-addr_len..]);
+    record.extend_from_slice(data);
+    record.push(checksum(&record));
+
+    buf.push(b'S');
+    buf.push(record_type);
+    for byte in record {
+        buf.extend_from_slice(format!("{:02X}", byte).as_bytes());
+    }
+    buf.push(b'\n');
+}
+
+/* The byte count of a record is one byte, which leaves the S0 header 252 bytes after its address and checksum */
+const MAX_HEADER_LEN: usize = 252;
+
+pub fn write(image: &MemoryImage, header: &str, buf: &mut Vec<u8>) -> Result<(), String> {
+    if header.len() > MAX_HEADER_LEN {
+        return Err(format!("Module name of {} bytes is longer than the {} an S0 record holds", header.len(), MAX_HEADER_LEN));
+    }
+    let end: u64 = image.regions.last().map(|r| r.end()).unwrap_or(0);
+    let highest: u64 = u64::max(end.saturating_sub(1), image.entry.unwrap_or(0) as u64);
+    /* Smallest record types that can hold every address: S1/S9, S2/S8 or S3/S7 */
+    let (data_type, end_type) = match highest {
+        0..=0xffff      => (b'1', b'9'),
+        0x10000..=0xffffff => (b'2', b'8'),
+        _               => (b'3', b'7'),
+    };
+
+    write_record(b'0', 0, header.as_bytes(), buf);
+    let mut count: u32 = 0;
+    for region in &image.regions {
+        for (i, chunk) in region.data.chunks(RECORD_LEN).enumerate() {
+            write_record(data_type, region.addr + (i*RECORD_LEN) as u32, chunk, buf);
+            count += 1;
+        }
+    }
+    match count {
+        0..=0xffff      => write_record(b'5', count, &[], buf),
+        0x10000..=0xffffff => write_record(b'6', count, &[], buf),
+        _               => {},
+    }
+    write_record(end_type, image.entry.unwrap_or(0), &[], buf);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::MemoryImage;
+    use crate::srec;
+
+    #[test]
+    fn test_srec() {
+        let mut image = MemoryImage::new();
+        image.insert(0x10000000, &(0..20).collect::<Vec<u8>>());
+        image.entry = Some(0x10000119);
+
+        let mut buf = Vec::<u8>::new();
+        srec::write(&image, "blink", &mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf.clone()).unwrap(), "\
+S0080000626C696E6BE7
+S31510000000000102030405060708090A0B0C0D0E0F62
+S309100000101011121390
+S5030002FA
+S70510000119D0
+");
+
+        let (parsed, header) = srec::parse(&buf).unwrap();
+        assert_eq!(header, "blink");
+        assert_eq!(parsed.regions.len(), 1);
+        assert_eq!(parsed.regions[0].data, image.regions[0].data);
+        assert_eq!(parsed.entry, Some(0x10000119));
+
+        let mut image = MemoryImage::new();
+        image.insert(0x100, &[0xaa]);
+        let mut buf = Vec::<u8>::new();
+        srec::write(&image, "", &mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "S0030000FC\nS1040100AA50\nS5030001FB\nS9030000FC\n");
+
+        assert!(srec::parse(b"S1040100AA51\n").is_err());
+        assert!(srec::parse(b"S5030002FA\n").is_err());
+        assert!(srec::parse("S1040100\u{e9}50\n".as_bytes()).is_err());
+        assert!(srec::parse(b"S307FFFFFFFFAABB97\n").is_err());
+        assert!(srec::parse(b"S307FFFFFFFEAABB98\n").is_ok());
+
+        let mut buf = Vec::<u8>::new();
+        srec::write(&image, &"m".repeat(252), &mut buf).unwrap();
+        assert_eq!(srec::parse(&buf).unwrap().1.len(), 252);
+        assert!(srec::write(&image, &"m".repeat(253), &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+use std::fmt;
+
+/* Memory map and UF2 constraints of a board or chip, ranges are [start, end) */
+#[derive(Clone)]
+pub struct Target {
+    pub name: String,
+    /* The first one is used unless the ELF machine picks another of them */
+    pub family_ids: Vec<u32>,
+    pub flash: Vec<(u32, u32)>,
+    pub ram: Vec<(u32, u32)>,
+    pub page_size: u32,
+    pub erase_size: u32,
+    /* Bootloader or SoftDevice, never written */
+    pub reserved: Vec<(u32, u32)>,
+    /* Prefixes of the Board-ID in INFO_UF2.TXT of matching bootloaders */
+    pub board_ids: Vec<String>
+}
+
+fn ranges(ranges: &[(u32, u32)]) -> String {
+    match ranges.is_empty() {
+        true => "none".to_string(),
+        false => ranges.iter().map(|(s, e)| format!("{:#010x}..{:#010x}", s, e)).collect::<Vec<_>>().join(", "),
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", "=".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Target", self.name)?;
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Family IDs",
+                 self.family_ids.iter().map(|id| format!("{:#x}", id)).collect::<Vec<_>>().join(", "))?;
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Flash", ranges(&self.flash))?;
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "RAM", ranges(&self.ram))?;
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Page size", self.page_size)?;
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Erase size", self.erase_size)?;
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Reserved", ranges(&self.reserved))?;
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Board IDs", match self.board_ids.is_empty() {
+            true => "none".to_string(),
+            false => self.board_ids.join(", "),
+        })
+    }
+}
+
+impl Target {
+    pub fn builtin() -> Vec<Target> {
+        let target = |name: &str, family_ids: &[u32], flash: (u32, u32), ram: (u32, u32),
+                      erase_size: u32, reserved: &[(u32, u32)], board_ids: &[&str]| Target {
+            name: name.to_string(),
+            family_ids: family_ids.to_vec(),
+            flash: vec![flash],
+            ram: vec![ram],
+            page_size: 256,
+            erase_size,
+            reserved: reserved.to_vec(),
+            board_ids: board_ids.iter().map(|b| b.to_string()).collect()
+        };
+        vec![
+            target("rp2040", &[0xe48bff56], (0x10000000, 0x11000000), (0x20000000, 0x20042000), 4096, &[],
+                   &["RPI-RP2"]),
+            target("rp2350", &[0xe48bff59, 0xe48bff5a, 0xe48bff5b], (0x10000000, 0x11000000),
+                   (0x20000000, 0x20082000), 4096, &[], &["RP2350"]),
+            target("samd21", &[0x68ed2b88], (0x00000000, 0x00040000), (0x20000000, 0x20008000), 256,
+                   &[(0x00000000, 0x00002000)], &["SAMD21"]),
+            target("samd51", &[0x55114460], (0x00000000, 0x00080000), (0x20000000, 0x20030000), 8192,
+                   &[(0x00000000, 0x00004000)], &["SAMD51"]),
+            /* S140 SoftDevice below the application, UF2 bootloader at the top */
+            target("nrf52840", &[0xada52840], (0x00000000, 0x00100000), (0x20000000, 0x20040000), 4096,
+                   &[(0x00000000, 0x00026000), (0x000f4000, 0x00100000)], &["nRF52840"]),
+            target("stm32f4", &[0x57755a57], (0x08000000, 0x08100000), (0x20000000, 0x20020000), 16384,
+                   &[(0x08000000, 0x08010000)], &["STM32F4"]),
+        ]
+    }
+
+    /* User profiles shadow built-in ones of the same name */
+    pub fn find(name: &str, user: &[Target]) -> Option<Target> {
+        let name = name.to_lowercase();
+        user.iter().find(|t| t.name.to_lowercase() == name).cloned()
+            .or_else(|| Target::builtin().into_iter().find(|t| t.name == name))
+    }
+
+    /* Profile whose Board-ID prefix matches, user profiles first */
+    pub fn for_board(board_id: &str, user: &[Target]) -> Option<Target> {
+        let board_id = board_id.to_lowercase();
+        user.iter().chain(Target::builtin().iter())
+            .find(|t| t.board_ids.iter().any(|b| board_id.starts_with(&b.to_lowercase())))
+            .cloned()
+    }
+
+    pub fn default_family_id(&self, machine_family_id: u32) -> u32 {
+        match self.family_ids.contains(&machine_family_id) {
+            true => machine_family_id,
+            false => self.family_ids.first().copied().unwrap_or(machine_family_id),
+        }
+    }
+
+    /* Flash or RAM, RP2040 style bootloaders load RAM-only images too */
+    pub fn contains(&self, start: u32, end: u64) -> bool {
+        self.flash.iter().chain(self.ram.iter()).any(|&(s, e)| start >= s && end <= e as u64)
+    }
+
+    pub fn parse_toml(text: &str) -> Result<Vec<Target>, String> {
+        let mut targets: Vec<Target> = Vec::new();
+        for (name, keys) in toml::parse(text)? {
+            let name = name.strip_prefix("target.").unwrap_or(&name).to_string();
+            let mut target = match keys.iter().find(|(k, _)| k == "inherits") {
+                Some((_, toml::Value::Str(base))) => Target::find(base, &targets)
+                    .ok_or(format!("[{}]: unknown target '{}' to inherit from", name, base))?,
+                Some(_) => return Err(format!("[{}]: inherits must be a string", name)),
+                None => Target {
+                    name: String::new(),
+                    family_ids: Vec::new(),
+                    flash: Vec::new(),
+                    ram: Vec::new(),
+                    page_size: 256,
+                    erase_size: 4096,
+                    reserved: Vec::new(),
+                    board_ids: Vec::new()
+                },
+            };
+            target.name = name.clone();
+            for (key, value) in &keys {
+                let error = || format!("[{}]: invalid value for {}", name, key);
+                match key.as_str() {
+                    "inherits" => {},
+                    "family_id" => target.family_ids = vec![value.u32().ok_or_else(error)?],
+                    "family_ids" => target.family_ids = value.u32s().ok_or_else(error)?,
+                    "flash" => target.flash = value.ranges().ok_or_else(error)?,
+                    "ram" => target.ram = value.ranges().ok_or_else(error)?,
+                    "reserved" => target.reserved = value.ranges().ok_or_else(error)?,
+                    "page_size" => target.page_size = value.u32().ok_or_else(error)?,
+                    "erase_size" => target.erase_size = value.u32().ok_or_else(error)?,
+                    "board_ids" => target.board_ids = value.strs().ok_or_else(error)?,
+                    _ => return Err(format!("[{}]: unknown key {}", name, key)),
+                }
+            }
+            if target.family_ids.is_empty() {
+                return Err(format!("[{}]: no family_id", name));
+            }
+            targets.push(target);
+        }
+        Ok(targets)
+    }
+}
+
+/* Just enough TOML for target files: tables, integers, basic strings and nested arrays,
+   anything else is rejected rather than misread */
+mod toml {
+    pub enum Value {
+        Int(u64),
+        Str(String),
+        Array(Vec<Value>)
+    }
+
+    impl Value {
+        pub fn u32(&self) -> Option<u32> {
+            match self {
+                Value::Int(i) => u32::try_from(*i).ok(),
+                _ => None,
+            }
+        }
+
+        pub fn strs(&self) -> Option<Vec<String>> {
+            match self {
+                Value::Array(values) => values.iter().map(|v| match v {
+                    Value::Str(s) => Some(s.clone()),
+                    _ => None,
+                }).collect(),
+                _ => None,
+            }
+        }
+
+        pub fn u32s(&self) -> Option<Vec<u32>> {
+            match self {
+                Value::Array(values) => values.iter().map(|v| v.u32()).collect(),
+                _ => None,
+            }
+        }
+
+        /* [[start, end], ...] */
+        pub fn ranges(&self) -> Option<Vec<(u32, u32)>> {
+            match self {
+                Value::Array(values) => values.iter().map(|v| match v {
+                    Value::Array(pair) if pair.len() == 2 => {
+                        let start = pair[0].u32()?;
+                        let end = pair[1].u32()?;
+                        (start <= end).then_some((start, end))
+                    },
+                    _ => None,
+                }).collect(),
+                _ => None,
+            }
+        }
+    }
+
+    fn parse_value(s: &str) -> Result<(Value, &str), String> {
+        let s = s.trim_start();
+        if let Some(rest) = s.strip_prefix('[') {
+            let mut values: Vec<Value> = Vec::new();
+            let mut rest = rest.trim_start();
+            loop {
+                if let Some(r) = rest.strip_prefix(']') {
+                    return Ok((Value::Array(values), r));
+                }
+                let (value, r) = parse_value(rest)?;
+                values.push(value);
+                rest = r.trim_start();
+                if let Some(r) = rest.strip_prefix(',') {
+                    rest = r.trim_start();
+                } else if !rest.starts_with(']') {
+                    return Err("expected ',' or ']' in array".to_string());
+                }
+            }
+        }
+        if s.starts_with("\"\"\"") || s.starts_with('\'') {
+            return Err("only \"basic\" strings are supported".to_string());
+        }
+        if s.starts_with('{') {
+            return Err("inline tables are not supported".to_string());
+        }
+        if let Some(rest) = s.strip_prefix('"') {
+            let end = rest.find('"').ok_or("unterminated string")?;
+            if rest[..end].contains('\\') {
+                return Err("escape sequences in strings are not supported".to_string());
+            }
+            return Ok((Value::Str(rest[..end].to_string()), &rest[end+1..]));
+        }
+        /* Floats, signs and dates are scanned too, to be rejected as a whole */
+        let end = s.find(|c: char| !(c.is_ascii_alphanumeric() || "_.+-:".contains(c))).unwrap_or(s.len());
+        if ["true", "false"].contains(&&s[..end]) {
+            return Err("booleans are not supported".to_string());
+        }
+        let number = s[..end].replace('_', "");
+        let value = match (number.strip_prefix("0x"), number.strip_prefix("0o"), number.strip_prefix("0b")) {
+            (Some(hex), _, _) => u64::from_str_radix(hex, 16),
+            (_, Some(octal), _) => u64::from_str_radix(octal, 8),
+            (_, _, Some(binary)) => u64::from_str_radix(binary, 2),
+            _ => number.parse::<u64>(),
+        }.map_err(|_| format!("unsupported value '{}', expected a non-negative integer, string or array", &s[..end]))?;
+        Ok((Value::Int(value), &s[end..]))
+    }
+
+    fn strip_comment(line: &str) -> &str {
+        let mut in_string = false;
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' => in_string = !in_string,
+                '#' if !in_string => return &line[..i],
+                _ => {},
+            }
+        }
+        line
+    }
+
+    fn is_bare_key(key: &str) -> bool {
+        !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    /* `name`, `"name"` or dotted bare keys such as `target.name` */
+    fn table_name(name: &str) -> Option<String> {
+        if let Some(quoted) = name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+            return (!quoted.is_empty() && !quoted.contains(['"', '\\'])).then(|| quoted.to_string());
+        }
+        name.split('.').all(|part| is_bare_key(part.trim()))
+            .then(|| name.split('.').map(|part| part.trim()).collect::<Vec<_>>().join("."))
+    }
+
+    pub type Table = (String, Vec<(String, Value)>);
+
+    pub fn parse(text: &str) -> Result<Vec<Table>, String> {
+        let mut tables: Vec<Table> = Vec::new();
+        let mut pending = String::new();
+        let mut start_line = 0;
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if pending.is_empty() {
+                start_line = n + 1;
+                if line.is_empty() {
+                    continue;
+                }
+                if line.starts_with("[[") {
+                    return Err(format!("line {}: arrays of tables are not supported, use one [table] per target", start_line));
+                }
+                if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                    let name = table_name(name.trim())
+                        .ok_or(format!("line {}: unsupported table name '{}'", start_line, name.trim()))?;
+                    if tables.iter().any(|(n, _)| *n == name) {
+                        return Err(format!("line {}: table [{}] defined twice", start_line, name));
+                    }
+                    tables.push((name, Vec::new()));
+                    continue;
+                }
+            }
+            /* Arrays may span several lines */
+            pending.push_str(line);
+            pending.push(' ');
+            if pending.matches('[').count() > pending.matches(']').count() {
+                continue;
+            }
+
+            let entry = std::mem::take(&mut pending);
+            let (key, value) = entry.split_once('=')
+                .ok_or(format!("line {}: expected key = value", start_line))?;
+            let key = key.trim();
+            if !is_bare_key(key) {
+                return Err(format!("line {}: unsupported key '{}'", start_line, key));
+            }
+            let (value, rest) = parse_value(value).map_err(|e| format!("line {}: {}", start_line, e))?;
+            if !rest.trim().is_empty() {
+                return Err(format!("line {}: unexpected '{}'", start_line, rest.trim()));
+            }
+            match tables.last_mut() {
+                Some((name, keys)) if keys.iter().any(|(k, _)| k == key) =>
+                    return Err(format!("line {}: key {} defined twice in [{}]", start_line, key, name)),
+                Some((_, keys)) => keys.push((key.to_string(), value)),
+                None => return Err(format!("line {}: key outside of a [table]", start_line)),
+            }
+        }
+        if !pending.is_empty() {
+            return Err(format!("line {}: unterminated array", start_line));
+        }
+        Ok(tables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::target::Target;
+
+    #[test]
+    fn test_targets() {
+        let text = "
+            # Application slot after our own bootloader
+            [target.myboard]
+            inherits = \"rp2040\"
+            reserved = [
+                [0x10000000, 0x10008000],   # bootloader
+            ]
+
+            [\"other\"]
+            family_ids = [0x1234_5678, 42]
+            flash = [[0, 0x1_0000]]
+            erase_size = 1024
+        ";
+        let targets = Target::parse_toml(text).unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].name, "myboard");
+        assert_eq!(targets[0].family_ids, vec![0xe48bff56]);
+        assert_eq!(targets[0].reserved, vec![(0x10000000, 0x10008000)]);
+        assert_eq!(targets[1].family_ids, vec![0x12345678, 42]);
+        assert_eq!(targets[1].flash, vec![(0, 0x10000)]);
+        assert_eq!((targets[1].page_size, targets[1].erase_size), (256, 1024));
+
+        assert!(Target::parse_toml("[a]\nfamily_id = [1, 2").is_err());
+        assert!(Target::parse_toml("[a]\nfamily_id = 1\nflash = [[2, 1]]").is_err());
+        assert!(Target::parse_toml("[a]\ncolour = 1").is_err());
+        assert!(Target::parse_toml("family_id = 1").is_err());
+        for unsupported in [
+            "[[target]]\nfamily_id = 1",
+            "[a]\nfamily_id = 1\nboard_ids = ['X']",
+            "[a]\nfamily_id = 1\nboard_ids = [\"X\\tY\"]",
+            "[a]\nfamily_id = 1\nlocked = true",
+            "[a]\nfamily_id = 1\nflash = { start = 0 }",
+            "[a]\n\"family_id\" = 1",
+            "[a]\nfamily_id = 1\nfamily_id = 2",
+            "[a b]\nfamily_id = 1",
+        ] {
+            assert!(Target::parse_toml(unsupported).is_err(), "{}", unsupported);
+        }
+        assert_eq!(Target::parse_toml("[a]\nfamily_id = 0b101").unwrap()[0].family_ids, vec![5]);
+
+        let rp2350 = Target::find("RP2350", &targets).unwrap();
+        assert_eq!(rp2350.default_family_id(0xe48bff5a), 0xe48bff5a);
+        assert_eq!(rp2350.default_family_id(0xe48bff58), 0xe48bff59);
+        assert!(rp2350.contains(0x20000000, 0x20000100));
+        assert!(!rp2350.contains(0x0, 0x100));
+        assert!(Target::find("myboard", &targets).is_some());
+        assert!(Target::find("esp32", &targets).is_none());
+
+        let targets = Target::parse_toml("[x]\nfamily_id = 1\nboard_ids = [\"RPI-RP2-X\"]").unwrap();
+        assert_eq!(Target::for_board("RPI-RP2-X1", &targets).unwrap().name, "x");
+        assert_eq!(Target::for_board("RPI-RP2", &targets).unwrap().name, "rp2040");
+        assert_eq!(Target::for_board("nrf52840-Feather-revD", &[]).unwrap().name, "nrf52840");
+        assert!(Target::for_board("ESP32S2", &[]).is_none());
+    }
+}
@@ -1,13 +1,120 @@
 use std::fmt;
 
+use crate::elf::slice_to_u32;
+use crate::md5::md5;
+
+pub const MAGIC_START0: u32     = 0x0a324655;
+pub const MAGIC_START1: u32     = 0x9e5d5157;
+pub const MAGIC_END: u32        = 0x0ab16f30;
+pub const NOT_MAIN_FLASH: u32   = 0x00000001;
+pub const FILE_CONTAINER: u32   = 0x00001000;
+pub const HAS_FAMILY_ID: u32    = 0x00002000;
+pub const MD5_CHECKSUM: u32     = 0x00004000;
+pub const EXTENSION_TAGS: u32   = 0x00008000;
+
+pub const TAG_VERSION: u32      = 0x9fc7bc;
+pub const TAG_DESCRIPTION: u32  = 0x650d9d;
+pub const TAG_PAGE_SIZE: u32    = 0x0be9f7;
+pub const TAG_SHA2: u32         = 0xb46db0;
+pub const TAG_DEVICE_ID: u32    = 0xc8a729;
+
+pub const BLOCK_SIZE: usize     = 512;
+pub const FILE_PAYLOAD_SIZE: usize = 256;
+/* Start address, length and MD5 digest at the very end of the data area */
+const MD5_OFFSET: usize         = 476 - 24;
+
+pub const KNOWN_FLAGS: u32      = NOT_MAIN_FLASH | FILE_CONTAINER | HAS_FAMILY_ID | MD5_CHECKSUM | EXTENSION_TAGS;
+
+pub fn flag_names(flags: u32) -> String {
+    let mut names: Vec<String> = Vec::new();
+    for (flag, name) in [
+        (NOT_MAIN_FLASH, "NOT_MAIN_FLASH"),
+        (FILE_CONTAINER, "FILE_CONTAINER"),
+        (HAS_FAMILY_ID, "HAS_FAMILY_ID"),
+        (MD5_CHECKSUM, "MD5_CHECKSUM"),
+        (EXTENSION_TAGS, "EXTENSION_TAGS"),
+    ] {
+        if flags & flag != 0 {
+            names.push(name.to_string());
+        }
+    }
+    if flags & !KNOWN_FLAGS != 0 {
+        names.push(format!("{:#x}", flags & !KNOWN_FLAGS));
+    }
+    if names.is_empty() {
+        names.push("none".to_string());
+    }
+    names.join(" | ")
+}
+
+pub struct Uf2Extension {
+    pub tag: u32,
+    pub data: Vec<u8>
+}
+
+impl Uf2Extension {
+    pub fn new(tag: u32, data: &[u8]) -> Uf2Extension {
+        Uf2Extension {
+            tag: tag & 0xffffff,
+            data: data.to_vec()
+        }
+    }
+
+    pub fn version(version: &str) -> Uf2Extension {
+        Uf2Extension::new(TAG_VERSION, version.as_bytes())
+    }
+
+    pub fn description(description: &str) -> Uf2Extension {
+        Uf2Extension::new(TAG_DESCRIPTION, description.as_bytes())
+    }
+
+    pub fn page_size(page_size: u32) -> Uf2Extension {
+        Uf2Extension::new(TAG_PAGE_SIZE, &page_size.to_le_bytes())
+    }
+
+    pub fn device_id(device_id: u32) -> Uf2Extension {
+        Uf2Extension::new(TAG_DEVICE_ID, &device_id.to_le_bytes())
+    }
+
+    /* Header byte holds the size including the 4 byte header, entries are padded to 4 bytes */
+    fn encoded_len(&self) -> usize {
+        (4 + self.data.len()).next_multiple_of(4)
+    }
+}
+
+impl fmt::Display for Uf2Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match self.tag {
+            TAG_VERSION | TAG_DESCRIPTION => String::from_utf8_lossy(&self.data).into_owned(),
+            TAG_PAGE_SIZE | TAG_DEVICE_ID if self.data.len() == 4 => format!("{:#x}", slice_to_u32(&self.data)),
+            _ => self.data.iter().map(|b| format!("{:02x}", b)).collect(),
+        };
+        let name = match self.tag {
+            TAG_VERSION     => "Firmware version".to_string(),
+            TAG_DESCRIPTION => "Description".to_string(),
+            TAG_PAGE_SIZE   => "Page size".to_string(),
+            TAG_SHA2        => "SHA-2 checksum".to_string(),
+            TAG_DEVICE_ID   => "Device type ID".to_string(),
+            _               => format!("Tag {:#08x}", self.tag),
+        };
+        writeln!(f, "{0: <32} | {1: <35}", name, value)
+    }
+}
+
+pub struct Uf2File {
+    pub name: String,
+    pub data: Vec<u8>
+}
+
+#[derive(Clone)]
 pub struct Uf2Block {
     pub magic_start0: u32,
     pub magic_start1: u32,
     pub flags: u32,
     pub target_addr: u32,
     pub payload_size: u32,
-    /*pub block_no: u32,
-    pub num_blocks: u32,*/
+    pub block_no: u32,
+    pub num_blocks: u32,
     pub family_id: u32,
     pub data: [u8; 476],
     pub magic_end: u32
@@ -21,13 +128,139 @@ impl Uf2Block {
             flags: 0,
             target_addr: 0,
             payload_size: 0,
-            /*block_no: 0,
-            num_blocks: 0,*/
+            block_no: 0,
+            num_blocks: 0,
             family_id: 0,
             data: [0; 476],
             magic_end: 0       
         }
     }
+
+    pub fn parse(data: &[u8]) -> Uf2Block {
+        assert_eq!(data.len(), BLOCK_SIZE);
+        let mut block = Uf2Block::new();
+        block.magic_start0 = slice_to_u32(&data[0..4]);
+        block.magic_start1 = slice_to_u32(&data[4..8]);
+        block.flags = slice_to_u32(&data[8..12]);
+        block.target_addr = slice_to_u32(&data[12..16]);
+        block.payload_size = slice_to_u32(&data[16..20]);
+        block.block_no = slice_to_u32(&data[20..24]);
+        block.num_blocks = slice_to_u32(&data[24..28]);
+        block.family_id = slice_to_u32(&data[28..32]);
+        block.data.copy_from_slice(&data[32..508]);
+        block.magic_end = slice_to_u32(&data[508..512]);
+        block
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic_start0 == MAGIC_START0
+            && self.magic_start1 == MAGIC_START1
+            && self.magic_end == MAGIC_END
+            && self.payload_size <= 476
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..usize::min(self.payload_size as usize, 476)]
+    }
+
+    /* File containers keep the file name right after the payload */
+    pub fn file_name(&self) -> Option<String> {
+        if self.flags & FILE_CONTAINER == 0 {
+            return None;
+        }
+        let name = &self.data[usize::min(self.payload_size as usize, 476)..];
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        Some(String::from_utf8_lossy(&name[..len]).into_owned())
+    }
+
+    /* End of the area usable for payload trailers such as extension tags */
+    fn trailer_end(&self) -> usize {
+        if self.flags & MD5_CHECKSUM != 0 {
+            MD5_OFFSET
+        } else {
+            476
+        }
+    }
+
+    pub fn set_extensions(&mut self, extensions: &[Uf2Extension]) -> Result<(), String> {
+        let start = (self.payload_size as usize).next_multiple_of(4);
+        let len: usize = extensions.iter().map(|e| e.encoded_len()).sum::<usize>() + 4;
+        if start + len > self.trailer_end() {
+            return Err(format!("{} bytes of extension tags do not fit after a {} byte payload",
+                               len, self.payload_size));
+        }
+
+        let mut i = start;
+        for extension in extensions {
+            let size = 4 + extension.data.len();
+            if size > 0xff {
+                return Err(format!("Extension tag {:#08x} is {} bytes, maximum is 255", extension.tag, size));
+            }
+            self.data[i] = size as u8;
+            self.data[i+1..i+4].copy_from_slice(&extension.tag.to_le_bytes()[..3]);
+            self.data[i+4..i+size].copy_from_slice(&extension.data);
+            self.data[i+size..i+extension.encoded_len()].fill(0);
+            i += extension.encoded_len();
+        }
+        self.data[i..i+4].fill(0);
+        self.flags |= EXTENSION_TAGS;
+        Ok(())
+    }
+
+    pub fn extensions(&self) -> Vec<Uf2Extension> {
+        let mut extensions: Vec<Uf2Extension> = Vec::new();
+        if self.flags & EXTENSION_TAGS == 0 {
+            return extensions;
+        }
+
+        let end = self.trailer_end();
+        let mut i = (self.payload_size as usize).next_multiple_of(4);
+        while i + 4 <= end {
+            let size = self.data[i] as usize;
+            if size < 4 || i + size > end {
+                break;
+            }
+            let tag = slice_to_u32(&self.data[i..i+4]) >> 8;
+            extensions.push(Uf2Extension::new(tag, &self.data[i+4..i+size]));
+            i += size.next_multiple_of(4);
+        }
+        extensions
+    }
+
+    fn extensions_end(&self) -> usize {
+        let start = (self.payload_size as usize).next_multiple_of(4);
+        match self.flags & EXTENSION_TAGS {
+            0 => self.payload_size as usize,
+            _ => start + self.extensions().iter().map(|e| e.encoded_len()).sum::<usize>() + 4,
+        }
+    }
+
+    pub fn set_md5(&mut self) -> Result<(), String> {
+        if self.extensions_end() > MD5_OFFSET {
+            return Err(format!("No room for an MD5 checksum in block at {:#x}", self.target_addr));
+        }
+        let digest = md5(self.payload());
+        self.data[MD5_OFFSET..MD5_OFFSET+4].copy_from_slice(&self.target_addr.to_le_bytes());
+        self.data[MD5_OFFSET+4..MD5_OFFSET+8].copy_from_slice(&self.payload_size.to_le_bytes());
+        self.data[MD5_OFFSET+8..].copy_from_slice(&digest);
+        self.flags |= MD5_CHECKSUM;
+        Ok(())
+    }
+
+    /* None when the block carries no checksum */
+    pub fn verify_md5(&self) -> Option<bool> {
+        if self.flags & MD5_CHECKSUM == 0 {
+            return None;
+        }
+        let addr = slice_to_u32(&self.data[MD5_OFFSET..MD5_OFFSET+4]);
+        let len = slice_to_u32(&self.data[MD5_OFFSET+4..MD5_OFFSET+8]) as usize;
+        /* The checksummed region has to be covered by this block's payload */
+        if addr < self.target_addr || (addr - self.target_addr) as usize + len > self.payload().len() {
+            return Some(false);
+        }
+        let start = (addr - self.target_addr) as usize;
+        Some(md5(&self.payload()[start..start+len]) == self.data[MD5_OFFSET+8..])
+    }
 }
 
 impl fmt::Display for Uf2Block {
@@ -37,10 +270,14 @@ impl fmt::Display for Uf2Block {
         writeln!(f, "{}", "-".repeat(70))?;
         writeln!(f, "{0: <32} | {1: <35}", "Magic start 1", format!("{:#x}", self.magic_start1))?;
         writeln!(f, "{}", "-".repeat(70))?;
-        writeln!(f, "{0: <32} | {1: <35}", "Flags", format!("{:#b}", self.flags))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Flags", format!("{:#x} ({})", self.flags, flag_names(self.flags)))?;
         writeln!(f, "{}", "-".repeat(70))?;
         writeln!(f, "{0: <32} | {1: <35}", "Target address", format!("{:#x}", self.target_addr))?;
         writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Payload size", self.payload_size)?;
+        writeln!(f, "{}", "-".repeat(70))?;
+        writeln!(f, "{0: <32} | {1: <35}", "Block number", format!("{} / {}", self.block_no, self.num_blocks))?;
+        writeln!(f, "{}", "-".repeat(70))?;
         writeln!(f, "{0: <32} | {1: <35}", "Family ID", format!("{:#x}", self.family_id))?;
         writeln!(f, "{}", "-".repeat(70))?;
         writeln!(f, "{0: <32} | {1: <35}", "Magic end", format!("{:#x}", self.magic_end))?;
@@ -48,6 +285,7 @@ impl fmt::Display for Uf2Block {
     }
 }
 
+#[derive(Clone)]
 pub struct Uf2 {
     pub blocks: Vec<Uf2Block>
 }
@@ -68,6 +306,169 @@ impl Uf2 {
         }
     }
 
+    pub fn is_uf2(data: &[u8]) -> bool {
+        data.len() >= 8
+            && slice_to_u32(&data[0..4]) == MAGIC_START0
+            && slice_to_u32(&data[4..8]) == MAGIC_START1
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Uf2, String> {
+        if !data.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(format!("UF2 size {} is not a multiple of {}", data.len(), BLOCK_SIZE));
+        }
+        let mut uf2 = Uf2::new();
+        for chunk in data.chunks(BLOCK_SIZE) {
+            uf2.blocks.push(Uf2Block::parse(chunk));
+        }
+        Ok(uf2)
+    }
+
+    pub fn family_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = Vec::new();
+        for block in &self.blocks {
+            if block.flags & FILE_CONTAINER != 0 {
+                continue;
+            }
+            if block.flags & HAS_FAMILY_ID != 0 && !ids.contains(&block.family_id) {
+                ids.push(block.family_id);
+            }
+        }
+        ids
+    }
+
+    /* One UF2 per family in order of appearance, blocks without a family ID (or file containers) under None */
+    pub fn split_by_family(&self) -> Vec<(Option<u32>, Uf2)> {
+        let mut parts: Vec<(Option<u32>, Uf2)> = Vec::new();
+        for block in &self.blocks {
+            let family_id = match block.flags & (HAS_FAMILY_ID | FILE_CONTAINER) {
+                HAS_FAMILY_ID => Some(block.family_id),
+                _ => None,
+            };
+            match parts.iter_mut().find(|(id, _)| *id == family_id) {
+                Some((_, uf2)) => uf2.blocks.push(block.clone()),
+                None => parts.push((family_id, Uf2 { blocks: vec![block.clone()] })),
+            }
+        }
+        parts
+    }
+
+    /* One UF2 per address range [start, end), holding the blocks whose target address falls inside it */
+    pub fn split_by_range(&self, ranges: &[(u32, u32)]) -> Vec<Uf2> {
+        ranges.iter().map(|&(start, end)| Uf2 {
+            blocks: self.blocks.iter()
+                .filter(|b| b.flags & FILE_CONTAINER == 0 && (start..end).contains(&b.target_addr))
+                .cloned().collect()
+        }).collect()
+    }
+
+    pub fn set_extensions(&mut self, extensions: &[Uf2Extension]) -> Result<(), String> {
+        match self.blocks.first_mut() {
+            Some(block) => block.set_extensions(extensions),
+            None => Err("Cannot add extension tags to an empty UF2".to_string()),
+        }
+    }
+
+    pub fn extensions(&self) -> Vec<Uf2Extension> {
+        let mut extensions: Vec<Uf2Extension> = Vec::new();
+        for block in &self.blocks {
+            for extension in block.extensions() {
+                if !extensions.iter().any(|e| e.tag == extension.tag && e.data == extension.data) {
+                    extensions.push(extension);
+                }
+            }
+        }
+        extensions
+    }
+
+    pub fn add_file(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        if name.is_empty() || name.len() + 1 > 476 - FILE_PAYLOAD_SIZE {
+            return Err(format!("File name '{}' must be 1 to {} bytes", name, 476 - FILE_PAYLOAD_SIZE - 1));
+        }
+        if data.len() > u32::MAX as usize {
+            return Err(format!("File '{}' is too large for a UF2 file container", name));
+        }
+
+        /* An empty file still gets one block to carry its name */
+        let n: usize = usize::max(data.len().div_ceil(FILE_PAYLOAD_SIZE), 1);
+        for i in 0..n {
+            let chunk = &data[i*FILE_PAYLOAD_SIZE..usize::min((i+1)*FILE_PAYLOAD_SIZE, data.len())];
+            let mut block = Uf2Block::new();
+            block.magic_start0 = MAGIC_START0;
+            block.magic_start1 = MAGIC_START1;
+            block.flags |= FILE_CONTAINER;
+            block.target_addr = (i*FILE_PAYLOAD_SIZE) as u32;
+            block.payload_size = chunk.len() as u32;
+            block.family_id = data.len() as u32;
+            block.data[..chunk.len()].copy_from_slice(chunk);
+            block.data[chunk.len()..chunk.len()+name.len()].copy_from_slice(name.as_bytes());
+            block.magic_end = MAGIC_END;
+            self.blocks.push(block);
+        }
+        Ok(())
+    }
+
+    pub fn files(&self) -> Result<Vec<Uf2File>, String> {
+        let blocks: Vec<(String, &Uf2Block)> = self.blocks.iter()
+            .filter(|b| b.is_valid())
+            .filter_map(|b| b.file_name().map(|name| (name, b)))
+            .collect();
+
+        /* Sizes come from untrusted blocks, they must agree and be covered by the payload present */
+        let mut sizes: Vec<(&str, u32, u64)> = Vec::new();
+        for (name, block) in &blocks {
+            match sizes.iter_mut().find(|(n, _, _)| n == name) {
+                Some((_, size, _)) if *size != block.family_id =>
+                    return Err(format!("Blocks of file '{}' declare sizes {} and {}", name, size, block.family_id)),
+                Some((_, _, present)) => *present += block.payload().len() as u64,
+                None => sizes.push((name, block.family_id, block.payload().len() as u64)),
+            }
+        }
+        if let Some((name, size, present)) = sizes.iter().find(|&&(_, size, present)| size as u64 > present) {
+            return Err(format!("File '{}' declares {} bytes but its blocks hold {}", name, size, present));
+        }
+
+        let mut files: Vec<Uf2File> = Vec::new();
+        for (name, block) in blocks {
+            let i = match files.iter().position(|f| f.name == name) {
+                Some(i) => i,
+                None => {
+                    files.push(Uf2File {
+                        name,
+                        data: vec![0; block.family_id as usize]
+                    });
+                    files.len() - 1
+                }
+            };
+
+            let file = &mut files[i];
+            let offset = block.target_addr as usize;
+            let payload = block.payload();
+            if offset as u64 + payload.len() as u64 > file.data.len() as u64 {
+                return Err(format!("Block at offset {:#x} exceeds the size of file '{}'", offset, file.name));
+            }
+            file.data[offset..offset+payload.len()].copy_from_slice(payload);
+        }
+        Ok(files)
+    }
+
+    pub fn set_md5(&mut self) -> Result<(), String> {
+        for block in &mut self.blocks {
+            block.set_md5()?;
+        }
+        Ok(())
+    }
+
+    /* Indices of blocks whose MD5 checksum does not match their payload */
+    pub fn verify_md5(&self) -> Vec<usize> {
+        let mut bad: Vec<usize> = Vec::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            if block.verify_md5() == Some(false) {
+                bad.push(i);
+            }
+        }
+        bad
+    }
+
     pub fn write(&self, buf: &mut Vec<u8>) {
         let num_blocks: u32 = self.blocks.len() as u32;
         for (i, block) in self.blocks.iter().enumerate() {
@@ -90,3 +491,135 @@ impl Uf2 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::uf2::*;
+
+    #[test]
+    fn test_extensions_roundtrip() {
+        let mut uf2 = Uf2::new();
+        let mut block = Uf2Block::new();
+        block.magic_start0 = MAGIC_START0;
+        block.magic_start1 = MAGIC_START1;
+        block.magic_end = MAGIC_END;
+        block.payload_size = 256;
+        block.data[..256].fill(0xaa);
+        uf2.blocks.push(block);
+        uf2.set_extensions(&[
+            Uf2Extension::version("1.2.3"),
+            Uf2Extension::description("blinky"),
+            Uf2Extension::page_size(4096),
+        ]).unwrap();
+
+        let mut buf = Vec::<u8>::new();
+        uf2.write(&mut buf);
+        let parsed = Uf2::parse(&buf).unwrap();
+        assert_eq!(parsed.blocks[0].flags & EXTENSION_TAGS, EXTENSION_TAGS);
+        assert_eq!(parsed.blocks[0].payload(), &[0xaa; 256][..]);
+
+        let extensions = parsed.extensions();
+        assert_eq!(extensions.len(), 3);
+        assert_eq!(extensions[0].tag, TAG_VERSION);
+        assert_eq!(extensions[0].data, b"1.2.3");
+        assert_eq!(extensions[1].tag, TAG_DESCRIPTION);
+        assert_eq!(extensions[1].data, b"blinky");
+        assert_eq!(extensions[2].tag, TAG_PAGE_SIZE);
+        assert_eq!(extensions[2].data, 4096u32.to_le_bytes());
+
+        let mut block = Uf2Block::new();
+        block.payload_size = 476;
+        assert!(block.set_extensions(&[Uf2Extension::version("1.0")]).is_err());
+    }
+
+    #[test]
+    fn test_md5_checksum() {
+        let mut uf2 = Uf2::new();
+        for i in 0..2 {
+            let mut block = Uf2Block::new();
+            block.magic_start0 = MAGIC_START0;
+            block.magic_start1 = MAGIC_START1;
+            block.magic_end = MAGIC_END;
+            block.target_addr = 0x10000000 + i*256;
+            block.payload_size = 256;
+            block.data[..256].fill(i as u8);
+            uf2.blocks.push(block);
+        }
+        uf2.set_extensions(&[Uf2Extension::version("1.0")]).unwrap();
+        uf2.set_md5().unwrap();
+
+        let mut buf = Vec::<u8>::new();
+        uf2.write(&mut buf);
+        let mut parsed = Uf2::parse(&buf).unwrap();
+        assert_eq!(parsed.blocks[1].verify_md5(), Some(true));
+        assert!(parsed.verify_md5().is_empty());
+        assert_eq!(parsed.extensions().len(), 1);
+
+        parsed.blocks[1].data[7] ^= 0xff;
+        assert_eq!(parsed.verify_md5(), vec![1]);
+    }
+
+    #[test]
+    fn test_file_container() {
+        let config: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let mut uf2 = Uf2::new();
+        uf2.add_file("config.json", &config).unwrap();
+        uf2.add_file("empty.txt", &[]).unwrap();
+        assert_eq!(uf2.blocks.len(), 4);
+        assert_eq!(uf2.blocks[2].target_addr, 512);
+        assert_eq!(uf2.blocks[2].payload_size, 88);
+        assert_eq!(uf2.blocks[2].family_id, 600);
+        assert!(uf2.family_ids().is_empty());
+
+        let mut buf = Vec::<u8>::new();
+        uf2.write(&mut buf);
+        let files = Uf2::parse(&buf).unwrap().files().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, "config.json");
+        assert_eq!(files[0].data, config);
+        assert_eq!(files[1].name, "empty.txt");
+        assert!(files[1].data.is_empty());
+
+        assert!(uf2.add_file(&"x".repeat(220), &config).is_err());
+
+        let mut forged = Uf2::parse(&buf).unwrap();
+        forged.blocks[3].family_id = 0xfffffff0;
+        assert!(forged.files().is_err());
+        let mut forged = Uf2::parse(&buf).unwrap();
+        forged.blocks[1].family_id = 601;
+        assert!(forged.files().is_err());
+    }
+
+    #[test]
+    fn test_flag_names() {
+        assert_eq!(flag_names(0), "none");
+        assert_eq!(flag_names(HAS_FAMILY_ID), "HAS_FAMILY_ID");
+        assert_eq!(flag_names(NOT_MAIN_FLASH | HAS_FAMILY_ID | 0x10), "NOT_MAIN_FLASH | HAS_FAMILY_ID | 0x10");
+    }
+
+    #[test]
+    fn test_split() {
+        let mut uf2 = Uf2::new();
+        for (addr, family_id) in [(0x1000, 0xe48bff59), (0x1000, 0xe48bff5a), (0x1100, 0xe48bff59), (0x2000, 0xe48bff5a)] {
+            let mut block = Uf2Block::new();
+            block.flags = HAS_FAMILY_ID;
+            block.target_addr = addr;
+            block.family_id = family_id;
+            uf2.blocks.push(block);
+        }
+        uf2.add_file("a.txt", b"a").unwrap();
+
+        let parts = uf2.split_by_family();
+        let ids: Vec<Option<u32>> = parts.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![Some(0xe48bff59), Some(0xe48bff5a), None]);
+        let mut buf = Vec::<u8>::new();
+        parts[1].1.write(&mut buf);
+        let parsed = Uf2::parse(&buf).unwrap();
+        assert_eq!(parsed.blocks.iter().map(|b| (b.target_addr, b.block_no, b.num_blocks)).collect::<Vec<_>>(),
+                   vec![(0x1000, 0, 2), (0x2000, 1, 2)]);
+
+        let parts = uf2.split_by_range(&[(0x1000, 0x1100), (0x1100, 0x3000)]);
+        assert_eq!(parts[0].blocks.len(), 2);
+        assert_eq!(parts[1].blocks.len(), 2);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::elf::Elf32;
+use crate::image::MemoryImage;
+use crate::uf2::*;
+
+pub struct Uf22Elf {
+    pub family_id: Option<u32>,
+    pub e_machine: Option<u16>
+}
+
+fn family_machine(family_id: u32) -> Option<u16> {
+    match family_id {
+        0xe48bff56                  => Some(40),  /* RP2040 */
+        0xe48bff59 | 0xe48bff5b     => Some(40),  /* RP2350 ARM secure / non-secure */
+        0xe48bff5a                  => Some(243), /* RP2350 RISC-V */
+        0x68ed2b88                  => Some(40),  /* SAMD21 */
+        0x55114460                  => Some(40),  /* SAMD51 */
+        0x1b57745f                  => Some(40),  /* nRF52 */
+        0xada52840                  => Some(40),  /* nRF52840 */
+        0x57755a57                  => Some(40),  /* STM32F4 */
+        0x5ee21072                  => Some(40),  /* STM32F1 */
+        0x00ff6919                  => Some(40),  /* STM32L4 */
+        0xbfdd4eee | 0xc47e5767     => Some(94),  /* ESP32-S2 / ESP32-S3 (Xtensa) */
+        0xd42ba06c                  => Some(243), /* ESP32-C3 */
+        _                           => None,
+    }
+}
+
+impl Uf22Elf {
+    pub fn new() -> Uf22Elf {
+        Uf22Elf {
+            family_id: None,
+            e_machine: None
+        }
+    }
+
+    pub fn convert(&mut self, data: &[u8], buf: &mut Vec<u8>) -> Result<(), String> {
+        let uf2 = Uf2::parse(data)?;
+
+        /* Older SAMD and nRF tools leave out family IDs, all blocks are used then */
+        let family_id: Option<u32> = match self.family_id {
+            Some(id) => Some(id),
+            None => {
+                let ids = uf2.family_ids();
+                match ids.len() {
+                    0 if self.e_machine.is_some() => None,
+                    0 => return Err("UF2 has no blocks with a family ID, select an ELF machine".to_string()),
+                    1 => Some(ids[0]),
+                    _ => {
+                        let ids: Vec<String> = ids.iter().map(|id| format!("{:#x}", id)).collect();
+                        return Err(format!("UF2 contains several families ({}), select one", ids.join(", ")));
+                    },
+                }
+            },
+        };
+
+        let e_machine: u16 = match (self.e_machine, family_id) {
+            (Some(e_machine), _) => e_machine,
+            (None, Some(id)) => family_machine(id)
+                .ok_or(format!("No known ELF machine for family {:#x}, select one", id))?,
+            (None, None) => unreachable!(),
+        };
+
+        let image = match family_id {
+            Some(id) => MemoryImage::from_uf2_family(&uf2, id),
+            None => MemoryImage::from_uf2(&uf2),
+        };
+        if image.regions.is_empty() {
+            return Err(match family_id {
+                Some(id) => format!("UF2 has no flash blocks for family {:#x}", id),
+                None => "UF2 has no flash blocks".to_string(),
+            });
+        }
+
+        let segments: Vec<(u32, &[u8])> = image.regions.iter()
+            .map(|r| (r.addr, r.data.as_slice())).collect();
+        Elf32::write(e_machine, image.regions[0].addr, &segments, buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::elf::Elf32;
+    use crate::image::MemoryImage;
+    use crate::uf2::{Uf2, HAS_FAMILY_ID};
+    use crate::uf22elf::Uf22Elf;
+
+    #[test]
+    fn test_uf22elf() {
+        let steps: Vec<(&str, u16)> = vec![
+            ("test/correct_arm.uf2", 40),
+            ("test/correct_riscv.uf2", 243),
+        ];
+
+        for (uf2, e_machine) in steps {
+            let data: Vec<u8> = fs::read(uf2)
+                            .expect("Should be a uf2 file given as input");
+            let mut buf = Vec::<u8>::new();
+            let mut uf22elf = Uf22Elf::new();
+            uf22elf.convert(&data, &mut buf).unwrap();
+
+            let mut elf = Elf32::new();
+            elf.parse_elf(&buf);
+            assert_eq!(elf.ehdr.e_machine, e_machine);
+
+            let expected = MemoryImage::from_uf2(&Uf2::parse(&data).unwrap());
+            let image = MemoryImage::from_elf(&elf, &buf);
+            assert_eq!(image.regions.len(), expected.regions.len());
+            for (r1, r2) in image.regions.iter().zip(expected.regions.iter()) {
+                assert_eq!(r1.addr, r2.addr);
+                assert_eq!(r1.data, r2.data);
+            }
+        }
+
+        let mut uf2 = Uf2::parse(&fs::read("test/correct_arm.uf2").unwrap()).unwrap();
+        for block in &mut uf2.blocks {
+            block.flags &= !HAS_FAMILY_ID;
+        }
+        let mut data = Vec::<u8>::new();
+        uf2.write(&mut data);
+        assert!(Uf22Elf::new().convert(&data, &mut Vec::new()).is_err());
+        let mut buf = Vec::<u8>::new();
+        let mut uf22elf = Uf22Elf::new();
+        uf22elf.e_machine = Some(40);
+        uf22elf.convert(&data, &mut buf).unwrap();
+        let mut elf = Elf32::new();
+        elf.parse_elf(&buf);
+        assert_eq!(MemoryImage::from_elf(&elf, &buf).regions.len(), MemoryImage::from_uf2(&uf2).regions.len());
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+
+use crate::elf::Elf32;
+use crate::elf2uf2::default_family_id;
+use crate::image::MemoryImage;
+use crate::target::Target;
+use crate::uf2::*;
+
+/* Range ends are u64, a range may end at 2^32 */
+pub enum Issue {
+    Mismatch { start: u32, end: u64 },
+    Missing { start: u32, end: u64 },
+    ExtraBlock { block: usize, addr: u32 },
+    FamilyId { block: usize, found: u32, expected: u32 },
+    BlockNumber { block: usize, block_no: u32, num_blocks: u32, expected: u32 }
+}
+
+pub struct Report {
+    pub issues: Vec<Issue>,
+    pub page_size: u32
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::Mismatch { start, end } => writeln!(f, "{0: <32} | {1: <35}", "Mismatching bytes",
+                format!("{:#010x}..{:#010x} ({} bytes)", start, end, end - *start as u64)),
+            Issue::Missing { start, end } => writeln!(f, "{0: <32} | {1: <35}", "Missing from UF2",
+                format!("{:#010x}..{:#010x} ({} bytes)", start, end, end - *start as u64)),
+            Issue::ExtraBlock { block, addr } => writeln!(f, "{0: <32} | {1: <35}", format!("Extra page (block {})", block),
+                format!("{:#010x}", addr)),
+            Issue::FamilyId { block, found, expected } => writeln!(f, "{0: <32} | {1: <35}", format!("Wrong family ID (block {})", block),
+                format!("{:#x}, expected {:#x}", found, expected)),
+            Issue::BlockNumber { block, block_no, num_blocks, expected } => writeln!(f, "{0: <32} | {1: <35}", format!("Bad numbering (block {})", block),
+                format!("{} / {}, expected {} / {}", block_no, num_blocks, block, expected)),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", "-".repeat(70))?;
+            write!(f, "{}", issue)?;
+        }
+        let missing_pages = self.missing_pages();
+        writeln!(f, "{}", "=".repeat(70))?;
+        match self.issues.is_empty() {
+            true => writeln!(f, "{0: <32} | {1: <35}", "Result", "UF2 matches ELF"),
+            false => writeln!(f, "{0: <32} | {1: <35}", "Result",
+                format!("{} issues, {} missing pages", self.issues.len(), missing_pages.len())),
+        }
+    }
+}
+
+impl Report {
+    pub fn missing_pages(&self) -> Vec<u32> {
+        let mut pages: Vec<u32> = Vec::new();
+        for issue in &self.issues {
+            if let Issue::Missing { start, end } = issue {
+                let mut page = (start - start % self.page_size) as u64;
+                while page < *end {
+                    if !pages.contains(&(page as u32)) {
+                        pages.push(page as u32);
+                    }
+                    page += self.page_size as u64;
+                }
+            }
+        }
+        pages
+    }
+}
+
+/* Byte ranges of `expected` that are missing from, or differ in, `actual` */
+fn compare(expected: &MemoryImage, actual: &MemoryImage, issues: &mut Vec<Issue>) {
+    for region in &expected.regions {
+        /* (differs, missing, start) of the run being collected */
+        let mut run: Option<(bool, bool, u32)> = None;
+        for (i, byte) in region.data.iter().enumerate() {
+            let addr = region.addr + i as u32;
+            let state = match actual.read(addr, 1) {
+                Some(b) => (b[0] != *byte, false),
+                None => (false, true),
+            };
+            if let Some((differs, missing, start)) = run {
+                if (differs, missing) == state {
+                    continue;
+                }
+                push_run(differs, missing, start, addr as u64, issues);
+            }
+            run = Some((state.0, state.1, addr));
+        }
+        if let Some((differs, missing, start)) = run {
+            push_run(differs, missing, start, region.end(), issues);
+        }
+    }
+}
+
+fn push_run(differs: bool, missing: bool, start: u32, end: u64, issues: &mut Vec<Issue>) {
+    if differs {
+        issues.push(Issue::Mismatch { start, end });
+    } else if missing {
+        issues.push(Issue::Missing { start, end });
+    }
+}
+
+/* The family checked is `family_id`, else the target's, else the only one in the UF2, else the ELF machine's */
+pub fn verify(elf_data: &[u8], uf2_data: &[u8], family_id: Option<u32>, target: Option<&Target>) -> Result<Report, String> {
+    let mut elf = Elf32::new();
+    elf.parse_elf(elf_data);
+    let expected = MemoryImage::from_elf(&elf, elf_data);
+    let uf2 = Uf2::parse(uf2_data)?;
+    let machine_family_id = default_family_id(elf.ehdr.e_machine);
+    let uf2_family_ids = uf2.family_ids();
+    let family_id: u32 = match (family_id, target, uf2_family_ids.as_slice()) {
+        (Some(id), _, _) => id,
+        (None, Some(target), _) => target.default_family_id(machine_family_id),
+        (None, None, &[id]) => id,
+        (None, None, _) => machine_family_id,
+    };
+
+    let mut report = Report {
+        issues: Vec::new(),
+        page_size: uf2.blocks.first().map(|b| b.payload_size).filter(|s| *s > 0).unwrap_or(256)
+    };
+
+    let num_blocks = uf2.blocks.len() as u32;
+    for (i, block) in uf2.blocks.iter().enumerate() {
+        if block.block_no != i as u32 || block.num_blocks != num_blocks {
+            report.issues.push(Issue::BlockNumber {
+                block: i, block_no: block.block_no, num_blocks: block.num_blocks, expected: num_blocks
+            });
+        }
+        if !block.is_valid() || block.flags & (NOT_MAIN_FLASH | FILE_CONTAINER) != 0 {
+            continue;
+        }
+        if block.flags & HAS_FAMILY_ID != 0 && block.family_id != family_id {
+            report.issues.push(Issue::FamilyId { block: i, found: block.family_id, expected: family_id });
+        }
+        let covered = expected.regions.iter().any(|r| {
+            (block.target_addr as u64) < r.end() && (r.addr as u64) < block.target_addr as u64 + block.payload_size as u64
+        });
+        if !covered {
+            report.issues.push(Issue::ExtraBlock { block: i, addr: block.target_addr });
+        }
+    }
+
+    compare(&expected, &MemoryImage::from_uf2_family(&uf2, family_id), &mut report.issues);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::Elf32;
+    use crate::elf2uf2::Elf2Uf2;
+    use crate::uf2::Uf2;
+    use crate::verify::*;
+
+    #[test]
+    fn test_verify() {
+        let text: Vec<u8> = (0..0x300).map(|i| i as u8).collect();
+        let mut elf = Vec::<u8>::new();
+        Elf32::write(40, 0x10000000, &[(0x10000000, &text), (0x10000300, &[1, 2, 3, 4])], &mut elf);
+
+        let mut buf = Vec::<u8>::new();
+        Elf2Uf2::new().convert(&elf, &mut buf).unwrap();
+        assert!(verify(&elf, &buf, None, None).unwrap().issues.is_empty());
+        assert_eq!(verify(&elf, &buf, Some(0xe48bff56), None).unwrap().issues.len(), 5);
+        let rp2040 = Target::find("rp2040", &[]).unwrap();
+        assert_eq!(verify(&elf, &buf, None, Some(&rp2040)).unwrap().issues.len(), 5);
+
+        let mut elf2uf2 = Elf2Uf2::new();
+        elf2uf2.set_target(rp2040.clone());
+        let mut rp2040_buf = Vec::<u8>::new();
+        elf2uf2.convert(&elf, &mut rp2040_buf).unwrap();
+        assert!(verify(&elf, &rp2040_buf, None, None).unwrap().issues.is_empty());
+        assert!(verify(&elf, &rp2040_buf, None, Some(&rp2040)).unwrap().issues.is_empty());
+
+        let mut uf2 = Uf2::parse(&buf).unwrap();
+        uf2.blocks[0].data[0x10] ^= 0xff;
+        uf2.blocks[1].family_id = 0xe48bff56;
+        uf2.blocks[3].target_addr = 0x20000000;
+        let mut buf = Vec::<u8>::new();
+        uf2.write(&mut buf);
+        buf[2*512+24] = 7; /* num_blocks of block 2 */
+
+        let report = verify(&elf, &buf, None, None).unwrap();
+        assert!(matches!(report.issues[0], Issue::FamilyId { block: 1, found: 0xe48bff56, .. }));
+        assert!(matches!(report.issues[1], Issue::BlockNumber { block: 2, num_blocks: 7, expected: 4, .. }));
+        assert!(matches!(report.issues[2], Issue::ExtraBlock { block: 3, addr: 0x20000000 }));
+        assert!(matches!(report.issues[3], Issue::Mismatch { start: 0x10000010, end: 0x10000011 }));
+        assert!(matches!(report.issues[4], Issue::Missing { start: 0x10000100, end: 0x10000200 }));
+        assert!(matches!(report.issues[5], Issue::Missing { start: 0x10000300, end: 0x10000304 }));
+        assert_eq!(report.issues.len(), 6);
+        assert_eq!(report.missing_pages(), vec![0x10000100, 0x10000300]);
+
+        let mut high = Vec::<u8>::new();
+        Elf32::write(40, 0xffffff00, &[(0xffffff00, &[1; 0x100])], &mut high);
+        let mut buf = Vec::<u8>::new();
+        Elf2Uf2::new().convert(&high, &mut buf).unwrap();
+        let mut uf2 = Uf2::parse(&buf).unwrap();
+        uf2.blocks[0].data[0xff] = 2;
+        let mut changed = Vec::<u8>::new();
+        uf2.write(&mut changed);
+        let report = verify(&high, &changed, None, None).unwrap();
+        assert!(matches!(report.issues[0], Issue::Mismatch { start: 0xffffffff, end: 0x100000000 }));
+        assert!(report.to_string().contains("(1 bytes)"));
+
+        uf2.blocks[0].payload_size = 0x80;
+        let mut truncated = Vec::<u8>::new();
+        uf2.write(&mut truncated);
+        let report = verify(&high, &truncated, None, None).unwrap();
+        assert!(matches!(report.issues[0], Issue::Missing { start: 0xffffff80, end: 0x100000000 }));
+        assert_eq!(report.missing_pages(), vec![0xffffff80]);
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Stamp {
+    pub size: u64,
+    pub modified: SystemTime
+}
+
+pub fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(Stamp {
+        size: metadata.len(),
+        modified: metadata.modified().ok()?
+    })
+}
+
+pub struct Watcher {
+    pub path: PathBuf,
+    /* Stamp of the last version handed out by `poll` */
+    last: Option<Stamp>,
+    /* Changed version seen by the previous poll, not yet known to be completely written */
+    pending: Option<Stamp>
+}
+
+impl Watcher {
+    pub fn new(path: &Path) -> Watcher {
+        Watcher {
+            path: path.to_path_buf(),
+            last: None,
+            pending: None
+        }
+    }
+
+    /* True once the file has changed and then kept its size and mtime for two polls in a row,
+       linkers write in several steps and the file may even be missing for a moment */
+    pub fn poll(&mut self) -> bool {
+        let current = match stamp(&self.path) {
+            Some(current) if Some(current) != self.last => current,
+            _ => {
+                self.pending = None;
+                return false;
+            },
+        };
+        if self.pending == Some(current) {
+            self.last = Some(current);
+            self.pending = None;
+            return true;
+        }
+        self.pending = Some(current);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::watch::*;
+
+    #[test]
+    fn test_watch() {
+        let path = std::env::temp_dir().join(format!("elf2uf2-watch-{}", std::process::id()));
+        let mut watcher = Watcher::new(&path);
+        assert!(!watcher.poll());
+
+        fs::write(&path, b"one").unwrap();
+        assert!(!watcher.poll());
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        /* Still being written on the second poll */
+        fs::write(&path, b"two and").unwrap();
+        assert!(!watcher.poll());
+        fs::write(&path, b"two and three").unwrap();
+        assert!(!watcher.poll());
+        assert!(watcher.poll());
+
+        /* Gone in between */
+        fs::write(&path, b"four").unwrap();
+        assert!(!watcher.poll());
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.poll());
+        fs::write(&path, b"four").unwrap();
+        assert!(!watcher.poll());
+        assert!(watcher.poll());
+        fs::remove_file(&path).unwrap();
+    }
+}